@@ -0,0 +1,272 @@
+use crate::*;
+use near_sdk::IntoStorageKey;
+
+#[derive(Serialize, NearSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CapitalAllocationView {
+    pub capital_id: u64,
+    pub owner_id: AccountId,
+    pub status: CapitalAllocationStatus,
+    pub positions: Vec<AssetPositionOutput>,
+    pub withdrawn: Vec<AssetPositionOutput>,
+    pub entry_timestamp: Timestamp,
+    pub exit_timestamp: Timestamp,
+    pub entry_value: AssetPositionOutput,
+    pub exit_value: Option<AssetPositionOutput>,
+    pub pnl_bps: Option<I128>,
+    pub is_matured: bool,
+    pub early_exit: Option<EarlyExitReason>,
+    pub funding: Vec<FundingSource>,
+    pub intents_deposit: Option<IntentsDeposit>,
+    pub pending_reductions: u32,
+}
+
+impl CapitalAllocationView {
+    fn new(capital_id: u64, capital: CapitalAllocation) -> Self {
+        Self {
+            capital_id,
+            is_matured: capital.is_matured(env::block_timestamp_ms()),
+            owner_id: capital.owner_id,
+            status: capital.status,
+            positions: capital.positions.iter().map(Into::into).collect(),
+            withdrawn: capital.withdrawn.iter().map(Into::into).collect(),
+            entry_timestamp: capital.entry_timestamp,
+            exit_timestamp: capital.exit_timestamp,
+            entry_value: AssetPositionOutput::from(&capital.entry_value),
+            exit_value: capital.exit_value.as_ref().map(Into::into),
+            pnl_bps: capital.pnl_bps,
+            early_exit: capital.early_exit,
+            funding: capital.funding,
+            intents_deposit: capital.intents_deposit,
+            pending_reductions: capital.pending_reductions,
+        }
+    }
+}
+
+fn owner_allocations_prefix(owner_id: &AccountId) -> Vec<u8> {
+    let mut prefix = StorageKey::AllocationsByOwner.into_storage_key();
+    prefix.extend(env::sha256(owner_id.as_bytes()));
+    prefix
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn get_capital_allocation(&self, capital_id: u64) -> CapitalAllocationView {
+        self.internal_get_allocation_view(capital_id)
+    }
+
+    // unknown ids are returned as `null` in their place
+    pub fn get_capital_allocations(
+        &self,
+        capital_ids: Vec<u64>,
+    ) -> Vec<Option<CapitalAllocationView>> {
+        assert!(
+            capital_ids.len() as u64 <= MAX_PAGE_LIMIT,
+            "Can't get more than {} allocations at once",
+            MAX_PAGE_LIMIT
+        );
+        capital_ids
+            .into_iter()
+            .map(|capital_id| {
+                self.internal_find_capital(capital_id)
+                    .map(|capital| CapitalAllocationView::new(capital_id, capital))
+            })
+            .collect()
+    }
+
+    pub fn get_allocations(
+        &self,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<CapitalAllocationView> {
+        let from_index = from_index.unwrap_or(0);
+        let to_index = from_index
+            .saturating_add(page_limit(limit))
+            .min(self.next_capital_id);
+        (from_index..to_index)
+            .map(|capital_id| self.internal_get_allocation_view(capital_id))
+            .collect()
+    }
+
+    pub fn get_allocations_by_owner(
+        &self,
+        owner_id: AccountId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<CapitalAllocationView> {
+        let Some(capital_ids) = self.allocations_by_owner.get(&owner_id) else {
+            return vec![];
+        };
+        capital_ids
+            .iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(page_limit(limit) as usize)
+            .map(|capital_id| self.internal_get_allocation_view(capital_id))
+            .collect()
+    }
+
+    // allocations which still hold their positions
+    pub fn get_active_allocations(
+        &self,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<CapitalAllocationView> {
+        self.active_allocations
+            .iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(page_limit(limit) as usize)
+            .map(|capital_id| self.internal_get_allocation_view(capital_id))
+            .collect()
+    }
+}
+
+impl Contract {
+    fn internal_get_allocation_view(&self, capital_id: u64) -> CapitalAllocationView {
+        CapitalAllocationView::new(capital_id, self.internal_get_capital(capital_id))
+    }
+
+    pub(crate) fn internal_index_owner_allocation(
+        &mut self,
+        owner_id: &AccountId,
+        capital_id: u64,
+    ) {
+        let mut capital_ids = self
+            .allocations_by_owner
+            .get(owner_id)
+            .unwrap_or_else(|| Vector::new(owner_allocations_prefix(owner_id)));
+        capital_ids.push(&capital_id);
+        self.allocations_by_owner.insert(owner_id, &capital_ids);
+    }
+
+    // called on every write of an allocation, keeps `active_allocations` in line with its status
+    pub(crate) fn internal_index_allocation_status(
+        &mut self,
+        capital_id: u64,
+        status: &CapitalAllocationStatus,
+    ) {
+        if *status == CapitalAllocationStatus::Active {
+            self.active_allocations.insert(&capital_id);
+        } else {
+            self.active_allocations.remove(&capital_id);
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::{fund_allocation, get_context};
+
+    // accounts(0) owns the vault, accounts(1) runs the agent, accounts(3) funds the allocations
+    fn get_contract() -> Contract {
+        testing_env!(get_context(accounts(0)).build());
+        let mut contract = Contract::new(
+            "agent.near".to_string(),
+            accounts(1),
+            accounts(0),
+            None,
+            None,
+        );
+        for account_id in [accounts(1), accounts(3), accounts(4)] {
+            contract.internal_storage_deposit(&account_id, NearToken::from_near(1).as_yoctonear());
+        }
+        contract.grant_role(accounts(1), Role::Operator);
+        contract
+    }
+
+    fn create_allocation(contract: &mut Contract, owner_id: AccountId) -> u64 {
+        testing_env!(get_context(accounts(1)).build());
+        let funding = fund_allocation(contract, 1_000);
+        contract.create_capital_allocation(owner_id, funding, None, Some(0))
+    }
+
+    fn capital_ids(views: Vec<CapitalAllocationView>) -> Vec<u64> {
+        views.iter().map(|view| view.capital_id).collect()
+    }
+
+    #[test]
+    fn test_get_allocations() {
+        let mut contract = get_contract();
+        for owner_id in [accounts(3), accounts(4), accounts(3)] {
+            create_allocation(&mut contract, owner_id);
+        }
+
+        assert_eq!(
+            capital_ids(contract.get_allocations(None, None)),
+            vec![0, 1, 2]
+        );
+        assert_eq!(
+            capital_ids(contract.get_allocations(Some(1), Some(1))),
+            vec![1]
+        );
+        assert!(contract.get_allocations(Some(5), None).is_empty());
+
+        let entries = contract.get_allocations_by_owner(accounts(3), None, None);
+        assert_eq!(capital_ids(entries), vec![0, 2]);
+        let entries = contract.get_allocations_by_owner(accounts(3), Some(1), Some(5));
+        assert_eq!(capital_ids(entries), vec![2]);
+        assert!(contract
+            .get_allocations_by_owner(accounts(2), None, None)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_page_limit() {
+        assert_eq!(page_limit(None), DEFAULT_PAGE_LIMIT);
+        assert_eq!(page_limit(Some(5)), 5);
+        assert_eq!(page_limit(Some(u64::MAX)), MAX_PAGE_LIMIT);
+    }
+
+    #[test]
+    fn test_get_active_allocations() {
+        let mut contract = get_contract();
+        let first = create_allocation(&mut contract, accounts(3));
+        let second = create_allocation(&mut contract, accounts(4));
+        let mut active = capital_ids(contract.get_active_allocations(None, None));
+        active.sort();
+        assert_eq!(active, vec![first, second]);
+
+        // an allocation without positions is withdrawn right away
+        testing_env!(get_context(accounts(1)).build());
+        contract.withdraw_capital(first);
+        assert_eq!(
+            capital_ids(contract.get_active_allocations(None, None)),
+            vec![second]
+        );
+        let entries = contract.get_active_allocations(None, None);
+        assert!(entries[0].owner_id == accounts(4));
+    }
+
+    #[test]
+    fn test_get_capital_allocations() {
+        let mut contract = get_contract();
+        let capital_id = create_allocation(&mut contract, accounts(3));
+        contract.withdraw_capital(capital_id);
+        contract.set_capital_exit_value(capital_id, U128(1_250), None);
+
+        let views = contract.get_capital_allocations(vec![capital_id, 7]);
+        assert_eq!(views.len(), 2);
+        assert!(views[1].is_none());
+        let view = views[0].as_ref().unwrap();
+        assert!(view.status == CapitalAllocationStatus::Withdrawn);
+        assert!(view.is_matured);
+        assert_eq!(view.entry_value.amount.0, 1_000);
+        assert_eq!(view.exit_value.as_ref().unwrap().amount.0, 1_250);
+        assert_eq!(view.pnl_bps.unwrap().0, 2_500);
+
+        // amounts don't lose precision in JS clients
+        let json = near_sdk::serde_json::to_value(view).unwrap();
+        assert_eq!(json["entry_value"]["amount"], "1000");
+        assert_eq!(json["pnl_bps"], "2500");
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't get more than 100 allocations at once")]
+    fn test_get_capital_allocations_above_limit() {
+        let contract = get_contract();
+        contract.get_capital_allocations((0..101).collect());
+    }
+}
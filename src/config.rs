@@ -0,0 +1,239 @@
+use crate::*;
+
+// exactly one network feature picks the defaults, testnet builds need `--no-default-features`
+#[cfg(all(feature = "mainnet", feature = "testnet"))]
+compile_error!("Features `mainnet` and `testnet` are mutually exclusive");
+#[cfg(not(any(feature = "mainnet", feature = "testnet")))]
+compile_error!("Either the `mainnet` or the `testnet` feature must be enabled");
+
+#[cfg(feature = "mainnet")]
+pub const DEFAULT_USDC_CONTRACT_ID: &str =
+    "17208628f84f5d6ad33f0da3bbbeb27ffcb398eac501a31bd6ad2011e36133a1";
+#[cfg(feature = "mainnet")]
+pub const DEFAULT_INTENTS_CONTRACT_ID: &str = "intents.near";
+
+#[cfg(feature = "testnet")]
+pub const DEFAULT_USDC_CONTRACT_ID: &str =
+    "3e2210e1184b45b64c8a434c0a7e7b23cc04ea7eb7a6c3c32520d03d4afcb8af";
+#[cfg(feature = "testnet")]
+pub const DEFAULT_INTENTS_CONTRACT_ID: &str = "intents.testnet";
+
+const DEFAULT_GAS_FT_TRANSFER: Gas = Gas::from_tgas(2);
+const DEFAULT_GAS_FT_TRANSFER_CALL: Gas = Gas::from_tgas(25);
+const DEFAULT_GAS_WITHDRAW_CAPITAL: Gas = Gas::from_tgas(10);
+const MAX_CONFIG_GAS: Gas = Gas::from_tgas(100);
+
+// network dependent accounts and the gas attached to calls into them
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct Config {
+    // entry value token of allocations and the token shares are minted for
+    pub usdc_contract_id: AccountId,
+    pub intents_contract_id: AccountId,
+    pub gas_ft_transfer: Gas,
+    pub gas_ft_transfer_call: Gas,
+    // spent by `withdraw_capital` itself, on top of the transfers and their callback
+    pub gas_withdraw_capital: Gas,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            usdc_contract_id: AccountId::from_str(DEFAULT_USDC_CONTRACT_ID).unwrap(),
+            intents_contract_id: AccountId::from_str(DEFAULT_INTENTS_CONTRACT_ID).unwrap(),
+            gas_ft_transfer: DEFAULT_GAS_FT_TRANSFER,
+            gas_ft_transfer_call: DEFAULT_GAS_FT_TRANSFER_CALL,
+            gas_withdraw_capital: DEFAULT_GAS_WITHDRAW_CAPITAL,
+        }
+    }
+}
+
+impl Config {
+    pub(crate) fn assert_valid(&self) {
+        for gas in [
+            self.gas_ft_transfer,
+            self.gas_ft_transfer_call,
+            self.gas_withdraw_capital,
+        ] {
+            assert!(
+                gas.as_gas() > 0 && gas <= MAX_CONFIG_GAS,
+                "Gas must be positive and at most {}",
+                MAX_CONFIG_GAS
+            );
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn set_config(&mut self, config: Config) {
+        self.require_role(Role::Owner);
+        config.assert_valid();
+        if config.usdc_contract_id != self.config.usdc_contract_id {
+            // outstanding shares are redeemed in the USDC they were minted for
+            assert_eq!(
+                self.shares.total_supply, 0,
+                "Can't change USDC while shares are outstanding"
+            );
+            assert_eq!(
+                self.shares_allocated, 0,
+                "Can't change USDC while share pool capital is allocated"
+            );
+            // the pool balance would be stranded in the old USDC
+            self.internal_sweep_share_pool();
+            if self.tokens.get(&config.usdc_contract_id).is_none() {
+                self.removed_tokens.remove(&config.usdc_contract_id);
+                self.tokens.insert(
+                    &config.usdc_contract_id,
+                    &TokenConfig {
+                        decimals: USDC_DECIMALS,
+                        min_deposit: U128::from(MIN_USDC_DEPOSIT),
+                    },
+                );
+            }
+        }
+        self.config = config;
+    }
+
+    pub fn get_config(&self) -> Config {
+        self.config.clone()
+    }
+}
+
+impl Contract {
+    pub(crate) fn usdc_contract_id(&self) -> AccountId {
+        self.config.usdc_contract_id.clone()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::get_context;
+
+    fn sandbox_config() -> Config {
+        Config {
+            usdc_contract_id: accounts(4),
+            intents_contract_id: accounts(5),
+            gas_ft_transfer: Gas::from_tgas(5),
+            gas_ft_transfer_call: Gas::from_tgas(30),
+            gas_withdraw_capital: Gas::from_tgas(15),
+        }
+    }
+
+    #[test]
+    fn test_config_at_new() {
+        testing_env!(get_context(accounts(0)).build());
+        let contract = Contract::new(
+            "agent.near".to_string(),
+            accounts(1),
+            accounts(0),
+            None,
+            Some(sandbox_config()),
+        );
+
+        let config = contract.get_config();
+        assert_eq!(config.usdc_contract_id, accounts(4));
+        assert_eq!(config.intents_contract_id, accounts(5));
+        assert_eq!(config.gas_ft_transfer_call, Gas::from_tgas(30));
+        // the configured USDC is whitelisted instead of the default one
+        assert!(contract.get_token(accounts(4)).is_some());
+        assert!(contract
+            .get_token(AccountId::from_str(DEFAULT_USDC_CONTRACT_ID).unwrap())
+            .is_none());
+    }
+
+    #[test]
+    fn test_set_config() {
+        testing_env!(get_context(accounts(0)).build());
+        let mut contract = Contract::new(
+            "agent.near".to_string(),
+            accounts(1),
+            accounts(0),
+            None,
+            None,
+        );
+        assert_eq!(
+            contract.get_config().usdc_contract_id.as_str(),
+            DEFAULT_USDC_CONTRACT_ID
+        );
+
+        contract.set_config(sandbox_config());
+        assert_eq!(
+            contract.get_config().gas_withdraw_capital,
+            Gas::from_tgas(15)
+        );
+        assert_eq!(
+            contract.get_token(accounts(4)).unwrap().decimals,
+            USDC_DECIMALS
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't change USDC while share pool capital is allocated")]
+    fn test_set_config_with_allocated_pool() {
+        testing_env!(get_context(accounts(0)).build());
+        let mut contract = Contract::new(
+            "agent.near".to_string(),
+            accounts(1),
+            accounts(0),
+            None,
+            None,
+        );
+        contract.shares_allocated = 500_000;
+        contract.set_config(sandbox_config());
+    }
+
+    #[test]
+    fn test_set_config_sweeps_share_pool() {
+        testing_env!(get_context(accounts(0)).build());
+        let mut contract = Contract::new(
+            "agent.near".to_string(),
+            accounts(1),
+            accounts(0),
+            None,
+            None,
+        );
+        let usdc = contract.usdc_contract_id();
+        contract.internal_set_balance(&share_pool_key(), &usdc, 300_000);
+
+        contract.set_config(sandbox_config());
+        assert_eq!(contract.internal_get_balance(&share_pool_key(), &usdc), 0);
+        assert_eq!(contract.get_treasury_balance(usdc).0, 300_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Gas must be positive")]
+    fn test_set_config_without_gas() {
+        testing_env!(get_context(accounts(0)).build());
+        let mut contract = Contract::new(
+            "agent.near".to_string(),
+            accounts(1),
+            accounts(0),
+            None,
+            None,
+        );
+        contract.set_config(Config {
+            gas_ft_transfer: Gas::from_gas(0),
+            ..sandbox_config()
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires Owner role")]
+    fn test_set_config_not_owner() {
+        testing_env!(get_context(accounts(0)).build());
+        let mut contract = Contract::new(
+            "agent.near".to_string(),
+            accounts(1),
+            accounts(0),
+            None,
+            None,
+        );
+        testing_env!(get_context(accounts(1)).build());
+        contract.set_config(sandbox_config());
+    }
+}
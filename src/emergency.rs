@@ -0,0 +1,188 @@
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    pub fn set_emergency_mode(&mut self, enabled: bool) {
+        self.require_role(Role::Guardian);
+        if self.emergency_mode != enabled {
+            self.emergency_mode = enabled;
+            VaultEvent::EmergencyModeToggled(EmergencyModeToggledEvent {
+                enabled,
+                account_id: env::predecessor_account_id(),
+            })
+            .emit();
+        }
+    }
+
+    pub fn is_emergency_mode(&self) -> bool {
+        self.emergency_mode
+    }
+
+    // pays the caller's whole balance out to them, ignores pauses
+    pub fn emergency_withdraw(&mut self, twitter_id: U128, token_id: AccountId) -> Promise {
+        assert!(self.emergency_mode, "Emergency mode is not enabled");
+        let key = TwitterNearAccount {
+            twitter_id: twitter_id.0,
+            near_account_id: env::predecessor_account_id(),
+        };
+        let balance = self.internal_get_balance(&key, &token_id);
+        assert!(balance > 0, "No balance to withdraw");
+        self.internal_withdraw(&key, &token_id, balance);
+
+        let receiver_id = key.near_account_id.clone();
+        self.internal_payout(key, token_id, U128::from(balance), receiver_id)
+    }
+}
+
+impl Contract {
+    pub(crate) fn assert_not_emergency(&self) {
+        assert!(!self.emergency_mode, "Vault is in emergency mode");
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_logs};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::{fund_allocation, get_context};
+
+    fn usdc() -> AccountId {
+        AccountId::from_str(DEFAULT_USDC_CONTRACT_ID).unwrap()
+    }
+
+    // accounts(3) deposited USDC, accounts(2) is the guardian, accounts(1) runs the agent
+    fn get_contract() -> Contract {
+        testing_env!(get_context(accounts(0)).build());
+        let mut contract = Contract::new(
+            "agent.near".to_string(),
+            accounts(1),
+            accounts(0),
+            None,
+            None,
+        );
+        contract.internal_storage_deposit(&accounts(1), NearToken::from_near(1).as_yoctonear());
+        contract.internal_storage_deposit(&accounts(3), NearToken::from_near(1).as_yoctonear());
+        contract.grant_role(accounts(1), Role::Operator);
+        contract.grant_role(accounts(2), Role::Guardian);
+        contract.deposit_ft(U128(42), accounts(3), usdc(), 1_000_000, None);
+        contract
+    }
+
+    fn enable_emergency_mode(contract: &mut Contract) {
+        testing_env!(get_context(accounts(2)).build());
+        contract.set_emergency_mode(true);
+    }
+
+    #[test]
+    fn test_emergency_withdraw() {
+        let mut contract = get_contract();
+        enable_emergency_mode(&mut contract);
+        assert!(contract.is_emergency_mode());
+        assert!(get_logs()[0].contains("\"event\":\"emergency_mode_toggled\""));
+
+        // pauses don't hold depositors back
+        contract.internal_grant_role(&accounts(2), Role::Pauser);
+        contract.set_paused(PausableOperation::Withdrawals, true);
+
+        testing_env!(get_context(accounts(3)).build());
+        contract.emergency_withdraw(U128(42), usdc());
+        assert_eq!(contract.get_usdc_balance(U128(42), accounts(3)).0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Emergency mode is not enabled")]
+    fn test_emergency_withdraw_without_emergency() {
+        let mut contract = get_contract();
+
+        testing_env!(get_context(accounts(3)).build());
+        contract.emergency_withdraw(U128(42), usdc());
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires Guardian role")]
+    fn test_set_emergency_mode_not_guardian() {
+        let mut contract = get_contract();
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.set_emergency_mode(true);
+    }
+
+    #[test]
+    #[should_panic(expected = "Vault is in emergency mode")]
+    fn test_no_allocations_in_emergency() {
+        let mut contract = get_contract();
+        enable_emergency_mode(&mut contract);
+
+        testing_env!(get_context(accounts(1)).build());
+        let funding = fund_allocation(&mut contract, 1_000);
+        contract.create_capital_allocation(accounts(1), funding, None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Vault is in emergency mode")]
+    fn test_no_capital_withdrawals_in_emergency() {
+        let mut contract = get_contract();
+        testing_env!(get_context(accounts(1)).build());
+        let funding = fund_allocation(&mut contract, 1_000);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, Some(0));
+        enable_emergency_mode(&mut contract);
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.withdraw_capital(capital_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "Vault is in emergency mode")]
+    fn test_no_exit_values_in_emergency() {
+        let mut contract = get_contract();
+        testing_env!(get_context(accounts(1)).build());
+        let funding = fund_allocation(&mut contract, 1_000);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, Some(0));
+        testing_env!(get_context(accounts(1))
+            .prepaid_gas(Gas::from_tgas(300))
+            .build());
+        contract.withdraw_capital(capital_id);
+        enable_emergency_mode(&mut contract);
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.set_capital_exit_value(capital_id, U128(1_100), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Vault is in emergency mode")]
+    fn test_no_operator_payouts_in_emergency() {
+        let mut contract = get_contract();
+        enable_emergency_mode(&mut contract);
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.withdraw_usdc(U128(42), accounts(3), None, None);
+    }
+
+    #[test]
+    fn test_deposits_rejected_in_emergency() {
+        let mut contract = get_contract();
+        enable_emergency_mode(&mut contract);
+
+        testing_env!(get_context(usdc()).build());
+        let msg = r#"{"Deposit":{"twitter_id":"42","input_tweet_id":null}}"#.to_string();
+        match contract.ft_on_transfer(accounts(3), U128(200_000), msg) {
+            PromiseOrValue::Value(unused) => assert_eq!(unused.0, 200_000),
+            PromiseOrValue::Promise(_) => panic!("Expected a value"),
+        }
+        assert!(get_logs()[0].contains("\"reason\":\"emergency_mode\""));
+    }
+
+    #[test]
+    fn test_pending_request_released_in_emergency() {
+        let mut contract = get_contract();
+        testing_env!(get_context(accounts(3)).build());
+        let request_id = contract.request_withdrawal(U128(42), usdc(), U128(400_000));
+        enable_emergency_mode(&mut contract);
+
+        testing_env!(get_context(accounts(3)).build());
+        contract.release_withdrawal(request_id);
+        assert!(contract.get_withdrawal_request(request_id).is_none());
+    }
+}
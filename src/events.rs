@@ -1,4 +1,209 @@
 use crate::*;
+use near_sdk::serde::Serializer;
+use near_sdk::{log, serde_json};
+
+const VAULT_EVENT_STANDARD: &str = "defai_vault";
+const VAULT_EVENT_VERSION: &str = "1.0.0";
+
+// NEP-297 events of the vault, logged next to the `run_agent` events consumed by the agent
+#[derive(Serialize)]
+#[serde(
+    crate = "near_sdk::serde",
+    tag = "event",
+    content = "data",
+    rename_all = "snake_case"
+)]
+pub enum VaultEvent {
+    Deposit(#[serde(serialize_with = "as_list")] DepositEvent),
+    DepositRejected(#[serde(serialize_with = "as_list")] DepositRejectedEvent),
+    Withdraw(#[serde(serialize_with = "as_list")] WithdrawEvent),
+    WithdrawalRequested(#[serde(serialize_with = "as_list")] WithdrawalRequestedEvent),
+    WithdrawalRejected(#[serde(serialize_with = "as_list")] WithdrawalRejectedEvent),
+    PauseToggled(#[serde(serialize_with = "as_list")] PauseToggledEvent),
+    EmergencyModeToggled(#[serde(serialize_with = "as_list")] EmergencyModeToggledEvent),
+    AllocationCreated(#[serde(serialize_with = "as_list")] AllocationCreatedEvent),
+    PositionAdded(#[serde(serialize_with = "as_list")] PositionAddedEvent),
+    PositionReduced(#[serde(serialize_with = "as_list")] PositionReducedEvent),
+    CapitalWithdrawn(#[serde(serialize_with = "as_list")] CapitalWithdrawnEvent),
+    ExitValueSet(#[serde(serialize_with = "as_list")] ExitValueSetEvent),
+    FeesCharged(#[serde(serialize_with = "as_list")] FeesChargedEvent),
+    LeaderboardUpdated(#[serde(serialize_with = "as_list")] LeaderboardUpdatedEvent),
+    SeasonCreated(#[serde(serialize_with = "as_list")] SeasonCreatedEvent),
+    SeasonClosed(#[serde(serialize_with = "as_list")] SeasonClosedEvent),
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct VaultEventLog<'a> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event: &'a VaultEvent,
+}
+
+impl VaultEvent {
+    pub fn emit(&self) {
+        let event = VaultEventLog {
+            standard: VAULT_EVENT_STANDARD,
+            version: VAULT_EVENT_VERSION,
+            event: self,
+        };
+        log!("EVENT_JSON:{}", serde_json::to_string(&event).unwrap());
+    }
+}
+
+// NEP-297 expects `data` to be a list
+fn as_list<T: Serialize, S: Serializer>(data: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    std::slice::from_ref(data).serialize(serializer)
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DepositEvent {
+    pub account_id: AccountId,
+    pub twitter_id: U128,
+    pub token_id: AccountId,
+    pub amount: U128,
+    pub tweet_id: Option<U128>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DepositRejectedEvent {
+    pub sender_id: AccountId,
+    pub token_id: AccountId,
+    pub amount: U128,
+    pub reason: DepositRejectReason,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WithdrawEvent {
+    pub account_id: AccountId,
+    pub twitter_id: U128,
+    pub token_id: AccountId,
+    pub amount: U128,
+    pub receiver_id: AccountId,
+    pub success: bool,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WithdrawalRequestedEvent {
+    pub request_id: u64,
+    pub account_id: AccountId,
+    pub twitter_id: U128,
+    pub token_id: AccountId,
+    pub amount: U128,
+    // the request is released without approval from then on
+    pub release_timestamp: Timestamp,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WithdrawalRejectedEvent {
+    pub request_id: u64,
+    pub account_id: AccountId,
+    pub token_id: AccountId,
+    pub amount: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PauseToggledEvent {
+    pub operation: PausableOperation,
+    pub paused: bool,
+    pub account_id: AccountId,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EmergencyModeToggledEvent {
+    pub enabled: bool,
+    pub account_id: AccountId,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AllocationCreatedEvent {
+    pub capital_id: u64,
+    pub owner_id: AccountId,
+    pub entry_value: AssetPositionOutput,
+    pub entry_timestamp: Timestamp,
+    pub exit_timestamp: Timestamp,
+    pub funding: Vec<FundingSource>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PositionAddedEvent {
+    pub capital_id: u64,
+    pub token_id: AccountId,
+    pub amount: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PositionReducedEvent {
+    pub capital_id: u64,
+    pub token_id: AccountId,
+    pub amount: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CapitalWithdrawnEvent {
+    pub capital_id: u64,
+    pub owner_id: AccountId,
+    pub status: CapitalAllocationStatus,
+    // amounts which landed on intents in this round
+    pub withdrawn: Vec<AssetPositionOutput>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ExitValueSetEvent {
+    pub capital_id: u64,
+    pub owner_id: AccountId,
+    pub entry_value: AssetPositionOutput,
+    pub exit_value: AssetPositionOutput,
+    pub pnl_bps: Option<I128>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeesChargedEvent {
+    pub capital_id: u64,
+    pub owner_id: AccountId,
+    pub token_id: AccountId,
+    pub management_fee: U128,
+    pub performance_fee: U128,
+    pub treasury_id: AccountId,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LeaderboardUpdatedEvent {
+    pub capital_id: u64,
+    pub near_account_id: AccountId,
+    pub pnl_bps: I128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SeasonCreatedEvent {
+    pub season_id: u64,
+    pub name: String,
+    pub start_timestamp: Timestamp,
+    pub end_timestamp: Timestamp,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SeasonClosedEvent {
+    pub season_id: u64,
+    pub leaderboard: Vec<LeaderboardItem>,
+}
 
 pub mod emit {
     use super::*;
@@ -58,3 +263,172 @@ pub mod option_u128_dec_format {
         serializer.serialize_str(&num.unwrap_or_default().to_string())
     }
 }
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_logs};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::{fund_allocation, get_context, set_promise_results};
+
+    // accounts(1) runs the agent and settles allocations
+    fn get_contract() -> Contract {
+        let mut contract = Contract::new(
+            "agent.near".to_string(),
+            accounts(1),
+            accounts(0),
+            None,
+            None,
+        );
+        contract.internal_storage_deposit(&accounts(1), NearToken::from_near(1).as_yoctonear());
+        contract.internal_storage_deposit(&accounts(3), NearToken::from_near(1).as_yoctonear());
+        contract.internal_grant_role(&accounts(1), Role::Operator);
+        contract
+    }
+
+    fn usdc() -> AccountId {
+        AccountId::from_str(DEFAULT_USDC_CONTRACT_ID).unwrap()
+    }
+
+    // parsed vault events, `run_agent` events of the agent are skipped
+    fn vault_events() -> Vec<serde_json::Value> {
+        get_logs()
+            .iter()
+            .filter_map(|log| log.strip_prefix("EVENT_JSON:"))
+            .map(|event| serde_json::from_str::<serde_json::Value>(event).unwrap())
+            .filter(|event| event["standard"] == VAULT_EVENT_STANDARD)
+            .collect()
+    }
+
+    #[test]
+    fn test_event_format() {
+        testing_env!(get_context(accounts(1)).build());
+        VaultEvent::PositionAdded(PositionAddedEvent {
+            capital_id: 7,
+            token_id: usdc(),
+            amount: U128(1_000),
+        })
+        .emit();
+
+        assert_eq!(
+            get_logs(),
+            vec![format!(
+                r#"EVENT_JSON:{{"standard":"defai_vault","version":"1.0.0","event":"position_added","data":[{{"capital_id":7,"token_id":"{}","amount":"1000"}}]}}"#,
+                DEFAULT_USDC_CONTRACT_ID
+            )]
+        );
+    }
+
+    #[test]
+    fn test_deposit_events() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let mut contract = get_contract();
+
+        contract.deposit_near(U128(42));
+        contract.deposit_ft(U128(42), accounts(3), usdc(), 200_000, Some(U128(7)));
+
+        // the agent is still notified through `run_agent`
+        assert_eq!(
+            get_logs()
+                .iter()
+                .filter(|log| log.contains("\"event\":\"run_agent\""))
+                .count(),
+            2
+        );
+
+        let events = vault_events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["event"], "deposit");
+        assert_eq!(events[0]["version"], VAULT_EVENT_VERSION);
+        assert_eq!(events[0]["data"][0]["token_id"], NATIVE_NEAR_TOKEN_ID);
+        assert_eq!(
+            events[0]["data"][0]["amount"],
+            NearToken::from_near(1).as_yoctonear().to_string()
+        );
+        assert_eq!(events[1]["data"][0]["account_id"], accounts(3).as_str());
+        assert_eq!(events[1]["data"][0]["amount"], "200000");
+        assert_eq!(events[1]["data"][0]["tweet_id"], "7");
+    }
+
+    #[test]
+    fn test_allocation_lifecycle_events() {
+        testing_env!(get_context(accounts(1)).build());
+        let mut contract = get_contract();
+
+        let funding = fund_allocation(&mut contract, 1_000);
+        let capital_id = contract.create_capital_allocation(accounts(3), funding, None, Some(0));
+        let events = vault_events();
+        assert_eq!(events[0]["event"], "allocation_created");
+        assert_eq!(events[0]["data"][0]["capital_id"], capital_id);
+        assert_eq!(events[0]["data"][0]["owner_id"], accounts(3).as_str());
+        assert_eq!(events[0]["data"][0]["entry_value"]["amount"], "1000");
+
+        testing_env!(get_context(usdc()).build());
+        let msg = format!(r#"{{"AddCapital":{{"capital_id":{}}}}}"#, capital_id);
+        contract.ft_on_transfer(accounts(1), U128(1_000), msg);
+        let events = vault_events();
+        assert_eq!(events[0]["event"], "position_added");
+        assert_eq!(events[0]["data"][0]["amount"], "1000");
+
+        testing_env!(get_context(accounts(1))
+            .prepaid_gas(Gas::from_tgas(300))
+            .build());
+        contract.withdraw_capital(capital_id);
+        set_promise_results(vec![PromiseResult::Successful(
+            serde_json::to_vec(&U128(1_000)).unwrap(),
+        )]);
+        contract.on_withdraw_capital(
+            capital_id,
+            vec![WithdrawalLeg {
+                position_index: 0,
+                amount: U128(1_000),
+            }],
+        );
+        let events = vault_events();
+        assert_eq!(events[0]["event"], "capital_withdrawn");
+        assert_eq!(events[0]["data"][0]["status"], "Withdrawn");
+        assert_eq!(events[0]["data"][0]["withdrawn"][0]["amount"], "1000");
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.set_capital_exit_value(capital_id, U128(1_200), None);
+        let events = vault_events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["event"], "exit_value_set");
+        assert_eq!(events[0]["data"][0]["exit_value"]["amount"], "1200");
+        assert_eq!(events[0]["data"][0]["pnl_bps"], "2000");
+        assert_eq!(events[1]["event"], "leaderboard_updated");
+        assert_eq!(
+            events[1]["data"][0]["near_account_id"],
+            accounts(3).as_str()
+        );
+    }
+
+    #[test]
+    fn test_no_leaderboard_event_when_not_ranked() {
+        testing_env!(get_context(accounts(1)).build());
+        let mut contract = get_contract();
+
+        for pnl_bps in [1_000, 2_000, 3_000, 4_000, 5_000] {
+            contract.leaderboard.add_item(
+                LeaderboardItem {
+                    near_account_id: accounts(1),
+                    pnl_bps: I128(pnl_bps),
+                    capital_id: 100 + pnl_bps as u64,
+                },
+                5,
+            );
+        }
+
+        let funding = fund_allocation(&mut contract, 1_000);
+        let capital_id = contract.create_capital_allocation(accounts(3), funding, None, Some(0));
+        contract.withdraw_capital(capital_id);
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.set_capital_exit_value(capital_id, U128(1_050), None);
+        let events = vault_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["event"], "exit_value_set");
+    }
+}
@@ -0,0 +1,338 @@
+use crate::*;
+
+const MAX_PERFORMANCE_FEE_BPS: u16 = 5_000;
+const MAX_MANAGEMENT_FEE_BPS: u16 = 1_000;
+const MS_PER_YEAR: u128 = 31_536_000_000;
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Default)]
+pub struct FeeConfig {
+    // share of the profit which lifts the owner above their high-water mark
+    pub performance_fee_bps: u16,
+    // yearly rate on the entry value for the time the capital was locked
+    pub management_fee_bps: u16,
+}
+
+// net PnL of an owner's settled allocations in one token, before performance fees
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Default)]
+pub struct HighWaterMark {
+    pub cumulative_pnl: I128,
+    pub peak: I128,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn set_fees(&mut self, performance_fee_bps: u16, management_fee_bps: u16) {
+        self.require_role(Role::Owner);
+        assert!(
+            performance_fee_bps <= MAX_PERFORMANCE_FEE_BPS,
+            "Performance fee can't exceed {} bps",
+            MAX_PERFORMANCE_FEE_BPS
+        );
+        assert!(
+            management_fee_bps <= MAX_MANAGEMENT_FEE_BPS,
+            "Management fee can't exceed {} bps",
+            MAX_MANAGEMENT_FEE_BPS
+        );
+        self.fees = FeeConfig {
+            performance_fee_bps,
+            management_fee_bps,
+        };
+    }
+
+    // the collected fees aren't tied to the account, the new treasury can withdraw them
+    pub fn set_treasury(&mut self, treasury_id: AccountId) {
+        self.require_role(Role::Owner);
+        self.treasury_id = treasury_id;
+    }
+
+    pub fn get_fees(&self) -> FeeConfig {
+        self.fees.clone()
+    }
+
+    pub fn get_treasury(&self) -> AccountId {
+        self.treasury_id.clone()
+    }
+
+    pub fn get_treasury_balance(&self, token_id: AccountId) -> U128 {
+        U128::from(self.treasury_balances.get(&token_id).unwrap_or(0))
+    }
+
+    pub fn get_high_water_mark(&self, account_id: AccountId, token_id: AccountId) -> HighWaterMark {
+        self.high_water_marks
+            .get(&(account_id, token_id))
+            .unwrap_or_default()
+    }
+
+    pub fn withdraw_treasury(&mut self, token_id: AccountId, amount: Option<U128>) -> Promise {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.treasury_id,
+            "Only the treasury can withdraw fees"
+        );
+        self.assert_not_paused(PausableOperation::Withdrawals);
+        let balance = self.treasury_balances.get(&token_id).unwrap_or(0);
+        assert!(balance > 0, "No balance to withdraw");
+        let amount = amount.unwrap_or(U128::from(balance));
+        assert!(amount.0 <= balance, "Not enough balance to withdraw");
+        self.treasury_balances
+            .insert(&token_id, &(balance - amount.0));
+
+        let receiver_id = self.treasury_id.clone();
+        let transfer = if token_id.as_str() == NATIVE_NEAR_TOKEN_ID {
+            Promise::new(receiver_id.clone()).transfer(NearToken::from_yoctonear(amount.0))
+        } else {
+            ext_ft::ext(token_id.clone())
+                .with_static_gas(self.config.gas_ft_transfer)
+                .with_attached_deposit(NearToken::from_yoctonear(1))
+                .ft_transfer(receiver_id.clone(), amount, None)
+        };
+        transfer.then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_RESOLVE_WITHDRAW)
+                .on_withdraw_treasury(token_id, amount, receiver_id),
+        )
+    }
+
+    #[private]
+    // puts the fees back if the transfer failed
+    pub fn on_withdraw_treasury(
+        &mut self,
+        token_id: AccountId,
+        amount: U128,
+        receiver_id: AccountId,
+    ) -> bool {
+        let success = is_promise_success();
+
+        if !success {
+            self.internal_credit_treasury(&token_id, amount.0);
+        }
+
+        VaultEvent::Withdraw(WithdrawEvent {
+            account_id: receiver_id.clone(),
+            twitter_id: U128(0),
+            token_id,
+            amount,
+            receiver_id,
+            success,
+        })
+        .emit();
+
+        success
+    }
+}
+
+impl Contract {
+    // one entry per token, paid by the vault as the treasury doesn't have to register
+    pub(crate) fn internal_credit_treasury(&mut self, token_id: &AccountId, amount: Balance) {
+        let balance = self.treasury_balances.get(token_id).unwrap_or(0);
+        self.treasury_balances.insert(token_id, &(balance + amount));
+    }
+
+    // credits the treasury with the fees on a settled allocation and returns their total
+    pub(crate) fn internal_charge_fees(
+        &mut self,
+        capital_id: u64,
+        capital: &CapitalAllocation,
+    ) -> Balance {
+        let exit_value = capital.exit_value.as_ref().unwrap();
+        let entry_amount = capital.entry_value.amount;
+
+        let locked_ms = env::block_timestamp_ms()
+            .min(capital.exit_timestamp)
+            .saturating_sub(capital.entry_timestamp);
+        let management_fee = mul_div(
+            entry_amount,
+            self.fees.management_fee_bps as u128 * locked_ms as u128,
+            BPS_DENOMINATOR * MS_PER_YEAR,
+        )
+        .min(exit_value.amount);
+
+        // only the part of the PnL above the previous peak pays the performance fee
+        let hwm_key = (capital.owner_id.clone(), exit_value.token_id.clone());
+        let mut hwm = self.high_water_marks.get(&hwm_key).unwrap_or_default();
+        hwm.cumulative_pnl.0 +=
+            exit_value.amount as i128 - entry_amount as i128 - management_fee as i128;
+        let performance_fee = if hwm.cumulative_pnl.0 > hwm.peak.0 {
+            let fee = (hwm.cumulative_pnl.0 - hwm.peak.0) as u128
+                * self.fees.performance_fee_bps as u128
+                / BPS_DENOMINATOR;
+            hwm.peak = hwm.cumulative_pnl;
+            fee
+        } else {
+            0
+        };
+        let initial_storage = env::storage_usage();
+        self.high_water_marks.insert(&hwm_key, &hwm);
+        self.internal_track_storage(&capital.storage_payer_id(), initial_storage);
+
+        let total_fee = management_fee + performance_fee;
+        if total_fee > 0 {
+            self.internal_credit_treasury(&exit_value.token_id, total_fee);
+
+            VaultEvent::FeesCharged(FeesChargedEvent {
+                capital_id,
+                owner_id: capital.owner_id.clone(),
+                token_id: exit_value.token_id.clone(),
+                management_fee: U128::from(management_fee),
+                performance_fee: U128::from(performance_fee),
+                treasury_id: self.treasury_id.clone(),
+            })
+            .emit();
+        }
+
+        total_fee
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_logs};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::{get_context, set_promise_results, settle};
+
+    const DAY_MS: u64 = 86_400_000;
+
+    fn usdc() -> AccountId {
+        AccountId::from_str(DEFAULT_USDC_CONTRACT_ID).unwrap()
+    }
+
+    // accounts(0) owns the vault, accounts(1) runs the agent, accounts(4) is the treasury
+    fn get_contract() -> Contract {
+        testing_env!(get_context(accounts(0)).build());
+        let mut contract = Contract::new(
+            "agent.near".to_string(),
+            accounts(1),
+            accounts(4),
+            None,
+            None,
+        );
+        contract.internal_storage_deposit(&accounts(1), NearToken::from_near(1).as_yoctonear());
+        contract.internal_storage_deposit(&accounts(3), NearToken::from_near(1).as_yoctonear());
+        contract.grant_role(accounts(1), Role::Operator);
+        contract.set_fees(2_000, 365);
+        contract.deposit_ft(U128(42), accounts(3), usdc(), 10_000_000, None);
+        contract
+    }
+
+    #[test]
+    fn test_performance_and_management_fees() {
+        let mut contract = get_contract();
+
+        // 3.65% a year for 10 days on 1 USDC is 0.001 USDC, 20% of the rest of the profit
+        settle(
+            &mut contract,
+            accounts(3),
+            1_000_000,
+            1_201_000,
+            10 * DAY_MS,
+        );
+        assert_eq!(contract.get_treasury_balance(usdc()).0, 1_000 + 40_000);
+        assert_eq!(
+            contract.get_usdc_balance(U128(42), accounts(3)).0,
+            10_000_000 + 1_201_000 - 41_000
+        );
+        let logs = get_logs();
+        assert!(logs
+            .iter()
+            .any(|log| log.contains("\"event\":\"fees_charged\"")
+                && log.contains("\"performance_fee\":\"40000\"")));
+
+        let hwm = contract.get_high_water_mark(accounts(3), usdc());
+        assert_eq!(hwm.cumulative_pnl.0, 200_000);
+        assert_eq!(hwm.peak.0, 200_000);
+    }
+
+    #[test]
+    fn test_high_water_mark() {
+        let mut contract = get_contract();
+        contract.set_fees(2_000, 0);
+
+        settle(&mut contract, accounts(3), 1_000_000, 1_100_000, 0);
+        assert_eq!(contract.get_treasury_balance(usdc()).0, 20_000);
+
+        // losses have to be recovered before the next performance fee
+        settle(&mut contract, accounts(3), 1_000_000, 950_000, 0);
+        settle(&mut contract, accounts(3), 1_000_000, 1_080_000, 0);
+        assert_eq!(contract.get_treasury_balance(usdc()).0, 20_000 + 6_000);
+
+        let hwm = contract.get_high_water_mark(accounts(3), usdc());
+        assert_eq!(hwm.cumulative_pnl.0, 130_000);
+        assert_eq!(hwm.peak.0, 130_000);
+    }
+
+    #[test]
+    fn test_withdraw_treasury() {
+        let mut contract = get_contract();
+        settle(&mut contract, accounts(3), 1_000_000, 1_100_000, 0);
+
+        testing_env!(get_context(accounts(4)).build());
+        contract.withdraw_treasury(usdc(), None);
+        assert_eq!(contract.get_treasury_balance(usdc()).0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the treasury can withdraw fees")]
+    fn test_withdraw_treasury_not_treasury() {
+        let mut contract = get_contract();
+        settle(&mut contract, accounts(3), 1_000_000, 1_100_000, 0);
+
+        testing_env!(get_context(accounts(3)).build());
+        contract.withdraw_treasury(usdc(), None);
+    }
+
+    #[test]
+    fn test_on_withdraw_treasury_failure() {
+        let mut contract = get_contract();
+        settle(&mut contract, accounts(3), 1_000_000, 1_100_000, 0);
+
+        testing_env!(get_context(accounts(4)).build());
+        contract.withdraw_treasury(usdc(), Some(U128(5_000)));
+        assert_eq!(contract.get_treasury_balance(usdc()).0, 15_000);
+
+        set_promise_results(vec![PromiseResult::Failed]);
+        assert!(!contract.on_withdraw_treasury(usdc(), U128(5_000), accounts(4)));
+        assert_eq!(contract.get_treasury_balance(usdc()).0, 20_000);
+    }
+
+    #[test]
+    fn test_set_treasury_keeps_fees() {
+        let mut contract = get_contract();
+        settle(&mut contract, accounts(3), 1_000_000, 1_100_000, 0);
+
+        testing_env!(get_context(accounts(0)).build());
+        contract.set_treasury(accounts(5));
+        assert_eq!(contract.get_treasury(), accounts(5));
+        assert_eq!(contract.get_treasury_balance(usdc()).0, 20_000);
+
+        testing_env!(get_context(accounts(5)).build());
+        contract.withdraw_treasury(usdc(), None);
+        assert_eq!(contract.get_treasury_balance(usdc()).0, 0);
+    }
+
+    #[test]
+    fn test_treasury_deposit_kept_apart_from_fees() {
+        let mut contract = get_contract();
+        contract.internal_storage_deposit(&accounts(4), NearToken::from_near(1).as_yoctonear());
+        contract.deposit_ft(U128(0), accounts(4), usdc(), 500_000, None);
+        settle(&mut contract, accounts(3), 1_000_000, 1_100_000, 0);
+
+        assert_eq!(contract.get_usdc_balance(U128(0), accounts(4)).0, 500_000);
+        assert_eq!(contract.get_treasury_balance(usdc()).0, 20_000);
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.withdraw_usdc(U128(0), accounts(4), None, None);
+        assert_eq!(contract.get_usdc_balance(U128(0), accounts(4)).0, 0);
+        assert_eq!(contract.get_treasury_balance(usdc()).0, 20_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Performance fee can't exceed 5000 bps")]
+    fn test_set_fees_above_max() {
+        let mut contract = get_contract();
+        contract.set_fees(5_001, 0);
+    }
+}
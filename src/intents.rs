@@ -0,0 +1,369 @@
+use crate::*;
+
+// signed intent executed by intents.near right after a position lands, e.g. a token swap
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct SwapIntent {
+    pub token_id: AccountId,
+    // signed payload as JSON, passed through unchanged
+    pub intent: String,
+}
+
+// how intents.near credits the positions of an allocation, empty `msg` credits the vault
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct IntentsDeposit {
+    // account credited in intents, the vault by default
+    // other accounts must be allowed as withdrawal recipient by every depositor funding the allocation
+    pub receiver_id: Option<AccountId>,
+    // account credited in intents instead when a swap intent fails
+    pub refund_to: Option<AccountId>,
+    pub swap_intents: Vec<SwapIntent>,
+}
+
+// `msg` of `ft_transfer_call` to intents.near
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct IntentsDepositMsg {
+    receiver_id: AccountId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refund_to: Option<AccountId>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    execute_intents: Vec<serde_json::Value>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    refund_if_fails: bool,
+}
+
+impl IntentsDepositMsg {
+    pub fn new(receiver_id: AccountId) -> Self {
+        Self {
+            receiver_id,
+            refund_to: None,
+            execute_intents: vec![],
+            refund_if_fails: false,
+        }
+    }
+
+    pub fn refund_to(mut self, refund_to: AccountId) -> Self {
+        self.refund_to = Some(refund_to);
+        self
+    }
+
+    // a failed intent refunds the deposit instead of leaving it credited to `receiver_id`
+    pub fn swap_intent(mut self, intent: serde_json::Value) -> Self {
+        self.execute_intents.push(intent);
+        self.refund_if_fails = true;
+        self
+    }
+
+    pub fn build(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+impl CapitalAllocation {
+    // `msg` for sending the position in `token_id` to intents
+    pub fn intents_msg(&self, token_id: &AccountId) -> String {
+        let Some(deposit) = &self.intents_deposit else {
+            return "".to_string();
+        };
+        let receiver_id = deposit
+            .receiver_id
+            .clone()
+            .unwrap_or(env::current_account_id());
+        let mut msg = IntentsDepositMsg::new(receiver_id);
+        if let Some(refund_to) = &deposit.refund_to {
+            msg = msg.refund_to(refund_to.clone());
+        }
+        for swap in deposit
+            .swap_intents
+            .iter()
+            .filter(|swap| swap.token_id == *token_id)
+        {
+            msg = msg.swap_intent(serde_json::from_str(&swap.intent).unwrap());
+        }
+        msg.build()
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn set_intents_deposit(
+        &mut self,
+        capital_id: u64,
+        intents_deposit: Option<IntentsDeposit>,
+    ) {
+        self.require_role(Role::Agent);
+        let mut capital = self.internal_get_capital(capital_id);
+        assert!(
+            capital.status == CapitalAllocationStatus::Active,
+            "Capital Allocation is not active"
+        );
+        if let Some(deposit) = &intents_deposit {
+            // the agent can't redirect the positions to accounts the depositors didn't allow,
+            // the owner is picked by the agent so it doesn't count
+            for account_id in deposit.receiver_id.iter().chain(&deposit.refund_to) {
+                if *account_id != env::current_account_id() {
+                    assert!(
+                        !capital.funding.is_empty(),
+                        "Receiver is not an allowed withdrawal recipient"
+                    );
+                    for source in &capital.funding {
+                        self.assert_withdrawal_recipient(&source.near_account_id, account_id);
+                    }
+                }
+            }
+            assert!(
+                deposit.swap_intents.len() <= MAX_ASSETS_IN_CAPITAL_ALLOCATION,
+                "Too many swap intents in Capital Allocation"
+            );
+            for swap in &deposit.swap_intents {
+                assert!(
+                    serde_json::from_str::<serde_json::Value>(&swap.intent)
+                        .is_ok_and(|intent| intent.is_object()),
+                    "Swap intent must be a JSON object"
+                );
+            }
+        }
+
+        capital.intents_deposit = intents_deposit;
+        let initial_storage = env::storage_usage();
+        self.internal_set_capital(capital_id, &capital);
+        self.internal_track_storage(&capital.storage_payer_id(), initial_storage);
+        self.assert_storage_covered(&capital.storage_payer_id());
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::mock::MockAction;
+    use near_sdk::serde_json::{json, Value};
+    use near_sdk::test_utils::{accounts, get_created_receipts};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::{fund_allocation, get_context};
+
+    #[derive(Deserialize)]
+    #[serde(crate = "near_sdk::serde")]
+    struct MockDepositMessage {
+        receiver_id: AccountId,
+        refund_to: Option<AccountId>,
+        #[serde(default)]
+        execute_intents: Vec<Value>,
+        #[serde(default)]
+        refund_if_fails: bool,
+    }
+
+    // stands in for intents.near, reads `msg` the way its `ft_on_transfer` does
+    #[derive(Default)]
+    struct MockIntentsReceiver {
+        deposits: Vec<(AccountId, U128, MockDepositMessage)>,
+    }
+
+    impl MockIntentsReceiver {
+        fn ft_on_transfer(
+            &mut self,
+            token_id: AccountId,
+            sender_id: AccountId,
+            amount: U128,
+            msg: &str,
+        ) {
+            let message = if msg.is_empty() {
+                MockDepositMessage {
+                    receiver_id: sender_id,
+                    refund_to: None,
+                    execute_intents: vec![],
+                    refund_if_fails: false,
+                }
+            } else {
+                serde_json::from_str(msg).expect("Invalid intents deposit message")
+            };
+            self.deposits.push((token_id, amount, message));
+        }
+
+        // delivers the `ft_transfer_call`s to intents made in the current call
+        fn receive_transfers(&mut self) {
+            for receipt in get_created_receipts() {
+                for action in receipt.actions {
+                    let MockAction::FunctionCallWeight {
+                        method_name, args, ..
+                    } = action
+                    else {
+                        continue;
+                    };
+                    if method_name != b"ft_transfer_call" {
+                        continue;
+                    }
+                    let args: Value = serde_json::from_slice(&args).unwrap();
+                    assert_eq!(args["receiver_id"], DEFAULT_INTENTS_CONTRACT_ID);
+                    self.ft_on_transfer(
+                        receipt.receiver_id.clone(),
+                        accounts(0),
+                        serde_json::from_value(args["amount"].clone()).unwrap(),
+                        args["msg"].as_str().unwrap(),
+                    );
+                }
+            }
+        }
+    }
+
+    // accounts(1) runs the agent and owns an allocation holding accounts(4) and accounts(5) tokens
+    fn get_contract() -> (Contract, u64) {
+        testing_env!(get_context(accounts(0)).build());
+        let mut contract = Contract::new(
+            "agent.near".to_string(),
+            accounts(1),
+            accounts(0),
+            None,
+            None,
+        );
+        contract.internal_storage_deposit(&accounts(1), NearToken::from_near(1).as_yoctonear());
+        contract.internal_storage_deposit(&accounts(3), NearToken::from_near(1).as_yoctonear());
+        contract.grant_role(accounts(1), Role::Operator);
+
+        testing_env!(get_context(accounts(1)).build());
+        let key = TwitterNearAccount {
+            twitter_id: 42,
+            near_account_id: accounts(1),
+        };
+        let token_id = AccountId::from_str(DEFAULT_USDC_CONTRACT_ID).unwrap();
+        contract.internal_set_balance(&key, &token_id, 1_000);
+        let funding = vec![FundingSource {
+            twitter_id: U128(42),
+            near_account_id: accounts(1),
+            amount: U128(1_000),
+        }];
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, Some(0));
+        let msg = format!(r#"{{"AddCapital":{{"capital_id":{}}}}}"#, capital_id);
+        for token_id in [accounts(4), accounts(5)] {
+            testing_env!(get_context(token_id).build());
+            contract.ft_on_transfer(accounts(1), U128(500), msg.clone());
+        }
+        (contract, capital_id)
+    }
+
+    fn withdraw(contract: &mut Contract, capital_id: u64) -> MockIntentsReceiver {
+        testing_env!(get_context(accounts(1))
+            .prepaid_gas(Gas::from_tgas(300))
+            .build());
+        contract.withdraw_capital(capital_id);
+        let mut intents = MockIntentsReceiver::default();
+        intents.receive_transfers();
+        intents
+    }
+
+    #[test]
+    fn test_default_msg_credits_vault() {
+        let (mut contract, capital_id) = get_contract();
+        let intents = withdraw(&mut contract, capital_id);
+
+        assert_eq!(intents.deposits.len(), 2);
+        for (_, amount, message) in &intents.deposits {
+            assert_eq!(amount.0, 500);
+            assert_eq!(message.receiver_id, accounts(0));
+            assert!(message.execute_intents.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_msg_per_position() {
+        let (mut contract, capital_id) = get_contract();
+        let swap = json!({"signer_id": "agent.near", "payload": "swap", "signature": "ed25519:1"});
+        testing_env!(get_context(accounts(1)).build());
+        contract.add_withdrawal_recipient(accounts(2));
+        contract.add_withdrawal_recipient(accounts(3));
+        contract.set_intents_deposit(
+            capital_id,
+            Some(IntentsDeposit {
+                receiver_id: Some(accounts(2)),
+                refund_to: Some(accounts(3)),
+                swap_intents: vec![SwapIntent {
+                    token_id: accounts(5),
+                    intent: swap.to_string(),
+                }],
+            }),
+        );
+
+        let intents = withdraw(&mut contract, capital_id);
+        assert_eq!(intents.deposits.len(), 2);
+        for (token_id, _, message) in &intents.deposits {
+            assert_eq!(message.receiver_id, accounts(2));
+            assert_eq!(message.refund_to, Some(accounts(3)));
+            if *token_id == accounts(5) {
+                assert_eq!(message.execute_intents, vec![swap.clone()]);
+                assert!(message.refund_if_fails);
+            } else {
+                assert!(message.execute_intents.is_empty());
+                assert!(!message.refund_if_fails);
+            }
+        }
+    }
+
+    #[test]
+    fn test_builder() {
+        let msg = IntentsDepositMsg::new(accounts(2)).build();
+        assert_eq!(msg, r#"{"receiver_id":"charlie"}"#);
+
+        let msg = IntentsDepositMsg::new(accounts(2))
+            .refund_to(accounts(3))
+            .swap_intent(json!({"intent": "swap"}))
+            .build();
+        assert_eq!(
+            msg,
+            r#"{"receiver_id":"charlie","refund_to":"danny","execute_intents":[{"intent":"swap"}],"refund_if_fails":true}"#
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Receiver is not an allowed withdrawal recipient")]
+    fn test_receiver_not_allowed_by_owner() {
+        let (mut contract, capital_id) = get_contract();
+        testing_env!(get_context(accounts(1)).build());
+        contract.set_intents_deposit(
+            capital_id,
+            Some(IntentsDeposit {
+                receiver_id: Some(accounts(0)),
+                refund_to: Some(accounts(2)),
+                swap_intents: vec![],
+            }),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Receiver is not an allowed withdrawal recipient")]
+    fn test_agent_owned_allocation_receiver_not_allowed_by_depositor() {
+        let (mut contract, _) = get_contract();
+        // the agent owns an allocation funded by accounts(3)
+        testing_env!(get_context(accounts(1)).build());
+        let funding = fund_allocation(&mut contract, 1_000);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, Some(0));
+
+        contract.set_intents_deposit(
+            capital_id,
+            Some(IntentsDeposit {
+                receiver_id: Some(accounts(1)),
+                refund_to: None,
+                swap_intents: vec![],
+            }),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Swap intent must be a JSON object")]
+    fn test_invalid_swap_intent() {
+        let (mut contract, capital_id) = get_contract();
+        testing_env!(get_context(accounts(1)).build());
+        contract.set_intents_deposit(
+            capital_id,
+            Some(IntentsDeposit {
+                receiver_id: None,
+                refund_to: None,
+                swap_intents: vec![SwapIntent {
+                    token_id: accounts(5),
+                    intent: "swap".to_string(),
+                }],
+            }),
+        );
+    }
+}
@@ -1,147 +1,312 @@
 use crate::*;
-const MAX_LEADERBOARD_LENGTH: usize = 5;
+use near_sdk::collections::TreeMap;
+use near_sdk::IntoStorageKey;
+
+pub const DEFAULT_LEADERBOARD_LENGTH: u32 = 5;
+// every ranked item is a node of the tree, keep inserts and views within gas
+const MAX_LEADERBOARD_LENGTH: u32 = 100;
 
 #[near(serializers = [json, borsh])]
 #[derive(Clone)]
 pub struct LeaderboardItem {
     pub near_account_id: AccountId,
-    pub value: u128,
+    // signed return of the Capital Allocation in basis points
+    pub pnl_bps: I128,
     pub capital_id: u64,
 }
 
-#[near(serializers = [json, borsh])]
+#[near(serializers = [json])]
+pub enum LeaderboardKind {
+    Allocations,
+    Accounts,
+    Season(u64),
+}
+
+// ascending by return, ties rank the earlier allocation higher
+type LeaderboardKey = (i128, u64);
+
+fn leaderboard_key(item: &LeaderboardItem) -> LeaderboardKey {
+    (item.pnl_bps.0, u64::MAX - item.capital_id)
+}
+
+// best returns first, the worst item is evicted once the leaderboard is full
+#[near(serializers = [borsh])]
 pub struct Leaderboard {
-    pub profit: Vec<LeaderboardItem>,
-    pub loss: Vec<LeaderboardItem>,
+    items: TreeMap<LeaderboardKey, LeaderboardItem>,
 }
 
 impl Leaderboard {
-    pub fn new() -> Self {
+    pub fn new<S: IntoStorageKey>(prefix: S) -> Self {
         Self {
-            profit: vec![],
-            loss: vec![],
+            items: TreeMap::new(prefix),
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.items.len()
+    }
+
+    // returns whether the item made it onto the leaderboard
+    pub fn add_item(&mut self, item: LeaderboardItem, max_length: u32) -> bool {
+        let key = leaderboard_key(&item);
+        if self.items.len() >= max_length as u64
+            && self.items.min().is_some_and(|worst| key <= worst)
+        {
+            return false;
         }
+
+        self.items.insert(&key, &item);
+        self.truncate(max_length);
+        true
+    }
+
+    // ranks the item without a length limit
+    pub fn insert_item(&mut self, item: LeaderboardItem) {
+        self.items.insert(&leaderboard_key(&item), &item);
     }
 
-    pub fn add_item(&mut self, item: LeaderboardItem, is_profit: bool) {
-        let list = if is_profit {
-            &mut self.profit
-        } else {
-            &mut self.loss
-        };
+    pub fn remove_item(&mut self, item: &LeaderboardItem) {
+        self.items.remove(&leaderboard_key(item));
+    }
 
-        if list.len() < MAX_LEADERBOARD_LENGTH || item.clone().value > list.last().unwrap().value {
-            list.push(item);
-            list.sort_by(|a, b| b.value.cmp(&a.value));
-            if list.len() > MAX_LEADERBOARD_LENGTH {
-                list.pop();
-            }
+    pub fn truncate(&mut self, max_length: u32) {
+        while self.items.len() > max_length as u64 {
+            let worst = self.items.min().unwrap();
+            self.items.remove(&worst);
         }
     }
+
+    pub fn page(&self, from_index: u64, limit: u64) -> Vec<LeaderboardItem> {
+        self.items
+            .iter_rev()
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .map(|(_, item)| item)
+            .collect()
+    }
+
+    pub fn to_vec(&self) -> Vec<LeaderboardItem> {
+        self.page(0, self.len())
+    }
 }
 
-impl Default for Leaderboard {
-    fn default() -> Self {
-        Self::new()
+// signed return in basis points, `None` when nothing was put in
+pub fn pnl_bps(entry_amount: Balance, exit_amount: Balance) -> Option<i128> {
+    if entry_amount == 0 {
+        return None;
     }
+    let bps = |diff: Balance| {
+        let bps = U256::from(diff) * U256::from(BPS_DENOMINATOR) / U256::from(entry_amount);
+        bps.min(U256::from(i128::MAX as u128)).as_u128() as i128
+    };
+    Some(if exit_amount >= entry_amount {
+        bps(exit_amount - entry_amount)
+    } else {
+        -bps(entry_amount - exit_amount)
+    })
 }
 
+#[near_bindgen]
+impl Contract {
+    // shorter lengths drop the worst items of the all-time and open season leaderboards,
+    // the account leaderboard ranks every account and only shows fewer
+    pub fn set_leaderboard_length(&mut self, length: u32) {
+        self.require_role(Role::Owner);
+        assert!(
+            (1..=MAX_LEADERBOARD_LENGTH).contains(&length),
+            "Leaderboard length must be between 1 and {}",
+            MAX_LEADERBOARD_LENGTH
+        );
+        self.leaderboard_length = length;
+
+        self.leaderboard.truncate(length);
+        for season_id in self.open_seasons.clone() {
+            let mut season = self.seasons.get(season_id).unwrap();
+            season.leaderboard.truncate(length);
+            self.seasons.replace(season_id, &season);
+        }
+    }
+
+    pub fn get_leaderboard_length(&self) -> u32 {
+        self.leaderboard_length
+    }
+
+    pub fn get_leaderboard_page(
+        &self,
+        kind: LeaderboardKind,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<LeaderboardItem> {
+        let from_index = from_index.unwrap_or(0);
+        let limit = limit
+            .unwrap_or(self.leaderboard_length as u64)
+            .min(MAX_PAGE_LIMIT);
+        match kind {
+            LeaderboardKind::Allocations => self.leaderboard.page(from_index, limit),
+            LeaderboardKind::Accounts => self.account_leaderboard.page(from_index, limit),
+            LeaderboardKind::Season(season_id) => self
+                .seasons
+                .get(season_id)
+                .expect("Season not found")
+                .leaderboard
+                .page(from_index, limit),
+        }
+    }
+}
 
 #[cfg(all(test, not(target_arch = "wasm32")))]
 mod tests {
     #[allow(unused_imports)]
     use near_contract_standards::fungible_token::{metadata::FT_METADATA_SPEC, Balance};
-    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::test_utils::accounts;
     use near_sdk::testing_env;
 
     use super::*;
-
-    fn get_context(predecessor_account_id: AccountId) -> VMContextBuilder {
-        let mut builder = VMContextBuilder::new();
-        builder
-            .current_account_id(accounts(0))
-            .signer_account_id(predecessor_account_id.clone())
-            .predecessor_account_id(predecessor_account_id);
-        builder
-    }
+    use crate::test_utils::get_context;
 
     fn get_contract() -> Contract {
+        testing_env!(get_context(accounts(0)).build());
         Contract::new(
             "agent.near".to_string(),
-            accounts(1).into(),
-            None
+            accounts(1),
+            accounts(0),
+            None,
+            None,
         )
     }
 
+    fn item(i: u64, pnl_bps: i128) -> LeaderboardItem {
+        LeaderboardItem {
+            near_account_id: AccountId::from_str(format!("user{}.near", i).as_str()).unwrap(),
+            pnl_bps: I128(pnl_bps),
+            capital_id: i,
+        }
+    }
+
     #[test]
     fn test_new() {
         let context = get_context(accounts(1));
         testing_env!(context.build());
         let contract = get_contract();
-        assert!(contract.leaderboard.profit.is_empty());
-        assert!(contract.leaderboard.loss.is_empty());
+        assert_eq!(contract.leaderboard.len(), 0);
+        assert_eq!(
+            contract.get_leaderboard_length(),
+            DEFAULT_LEADERBOARD_LENGTH
+        );
     }
 
     #[test]
-    fn test_add_item_profit() {
+    fn test_add_item_sorted_by_signed_pnl() {
         let context = get_context(accounts(1));
         testing_env!(context.build());
         let mut contract = get_contract();
 
-        let item = LeaderboardItem {
-            near_account_id: AccountId::from_str("bob.near").unwrap(),
-            value: 100,
-            capital_id: 1,
-        };
-        contract.leaderboard.add_item(item.clone(), true);
-        assert_eq!(contract.leaderboard.profit.len(), 1usize);
+        contract.leaderboard.add_item(item(1, -90), 5);
+        contract.leaderboard.add_item(item(2, 90), 5);
+        contract.leaderboard.add_item(item(3, 0), 5);
+        contract.leaderboard.add_item(item(4, -5_000), 5);
+        contract.leaderboard.add_item(item(5, 0), 5);
 
-        assert_eq!(contract.leaderboard.profit[0].value, item.value);
+        let values: Vec<(i128, u64)> = contract
+            .leaderboard
+            .to_vec()
+            .iter()
+            .map(|item| (item.pnl_bps.0, item.capital_id))
+            .collect();
+        assert_eq!(values, vec![(90, 2), (0, 3), (0, 5), (-90, 1), (-5_000, 4)]);
     }
 
     #[test]
-    fn test_add_item_loss() {
+    fn test_add_item_exceeds_max_length() {
         let context = get_context(accounts(1));
         testing_env!(context.build());
         let mut contract = get_contract();
 
-        let item = LeaderboardItem {
-            near_account_id: AccountId::from_str("bob.near").unwrap(),
-            value: 100,
-            capital_id: 1,
-        };
-        contract.leaderboard.add_item(item.clone(), false);
-        assert_eq!(contract.leaderboard.loss.len(), 1usize);
-        assert_eq!(contract.leaderboard.loss[0].value, item.value);
+        for i in 0..8 {
+            assert!(contract.leaderboard.add_item(item(i, 100 + i as i128), 5));
+        }
+        assert!(!contract.leaderboard.add_item(item(8, 102), 5));
+        assert!(!contract.leaderboard.add_item(item(9, -1), 5));
+
+        let items = contract.leaderboard.to_vec();
+        assert_eq!(items.len(), 5);
+        assert_eq!(items[0].pnl_bps.0, 107);
+        assert_eq!(items[4].pnl_bps.0, 103);
     }
 
     #[test]
-    fn test_add_item_exceeds_max_length() {
+    fn test_leaderboard_page() {
         let context = get_context(accounts(1));
         testing_env!(context.build());
         let mut contract = get_contract();
 
         for i in 0..8 {
-            let item = LeaderboardItem {
-                near_account_id: AccountId::from_str(format!("user{}.near", i).as_str()).unwrap(),
-                value: 100 + i as u128,
-                capital_id: i as u64,
-            };
-            contract.leaderboard.add_item(item, true);
-
-            let item = LeaderboardItem {
-                near_account_id: AccountId::from_str(format!("user{}.near", i).as_str()).unwrap(),
-                value: 110 + i as u128,
-                capital_id: i as u64,
-            };
-            contract.leaderboard.add_item(item, false);
+            contract.leaderboard.add_item(item(i, 100 + i as i128), 10);
+        }
+
+        let page = contract.get_leaderboard_page(LeaderboardKind::Allocations, Some(2), Some(3));
+        let values: Vec<i128> = page.iter().map(|item| item.pnl_bps.0).collect();
+        assert_eq!(values, vec![105, 104, 103]);
+        assert!(contract
+            .get_leaderboard_page(LeaderboardKind::Allocations, Some(8), None)
+            .is_empty());
+        assert!(contract
+            .get_leaderboard_page(LeaderboardKind::Accounts, None, None)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_set_leaderboard_length() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+
+        testing_env!(get_context(accounts(0)).build());
+        contract.set_leaderboard_length(20);
+        for i in 0..12 {
+            contract
+                .leaderboard
+                .add_item(item(i, i as i128), contract.leaderboard_length);
         }
-        assert_eq!(contract.leaderboard.profit.len(), MAX_LEADERBOARD_LENGTH);
-        assert_eq!(contract.leaderboard.profit[0].value, 107);
-        assert_eq!(contract.leaderboard.profit[4].value, 103);
+        assert_eq!(contract.get_leaderboard().len(), 12);
 
-        assert_eq!(contract.leaderboard.loss.len(), MAX_LEADERBOARD_LENGTH);
-        assert_eq!(contract.leaderboard.loss[0].value, 117);
-        assert_eq!(contract.leaderboard.loss[4].value, 113);
+        contract.set_leaderboard_length(3);
+        let values: Vec<i128> = contract
+            .get_leaderboard()
+            .iter()
+            .map(|item| item.pnl_bps.0)
+            .collect();
+        assert_eq!(values, vec![11, 10, 9]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    #[should_panic(expected = "Leaderboard length must be between 1 and 100")]
+    fn test_set_leaderboard_length_above_max() {
+        let mut contract = get_contract();
+        contract.set_leaderboard_length(101);
+    }
+
+    #[test]
+    fn test_remove_item() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+
+        contract.leaderboard.add_item(item(1, 500), 5);
+        contract.leaderboard.add_item(item(2, 300), 5);
+        contract.leaderboard.remove_item(&item(1, 500));
+
+        let items = contract.leaderboard.to_vec();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].capital_id, 2);
+    }
+
+    #[test]
+    fn test_pnl_bps() {
+        assert_eq!(pnl_bps(1_000, 1_009), Some(90));
+        assert_eq!(pnl_bps(1_000, 991), Some(-90));
+        assert_eq!(pnl_bps(1_000, 1_000), Some(0));
+        assert_eq!(pnl_bps(1_000, 0), Some(-10_000));
+        assert_eq!(pnl_bps(0, 1_000), None);
+    }
+}
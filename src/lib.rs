@@ -1,47 +1,125 @@
 use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
 use near_sdk::borsh::{BorshDeserialize, BorshSerialize};
-use near_sdk::collections::LookupMap;
-use near_sdk::json_types::U128;
+use near_sdk::collections::{LookupMap, UnorderedMap, UnorderedSet, Vector};
+use near_sdk::json_types::{I128, U128};
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{
     env, ext_contract, near, near_bindgen, AccountId, BorshStorageKey, Gas, NearSchema, NearToken,
-    PanicOnDefault, Promise, PromiseOrValue, Timestamp,
+    PanicOnDefault, Promise, PromiseOrValue, PromiseResult, StorageUsage, Timestamp,
 };
 use std::cmp::PartialEq;
 use std::str::FromStr;
 
+mod allocations;
+mod config;
+mod emergency;
 mod events;
+mod fees;
+mod intents;
 mod leaderboard;
+mod migration;
+mod pause;
+mod positions;
+mod roles;
+mod seasons;
+mod shares;
+mod stats;
+mod storage;
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod test_utils;
 mod token_receiver;
-
-use leaderboard::{Leaderboard, LeaderboardItem};
+mod tokens;
+mod withdrawals;
+
+use config::Config;
+#[cfg(test)]
+use config::{DEFAULT_INTENTS_CONTRACT_ID, DEFAULT_USDC_CONTRACT_ID};
+use events::{
+    AllocationCreatedEvent, CapitalWithdrawnEvent, DepositEvent, DepositRejectedEvent,
+    EmergencyModeToggledEvent, ExitValueSetEvent, FeesChargedEvent, LeaderboardUpdatedEvent,
+    PauseToggledEvent, PositionAddedEvent, PositionReducedEvent, SeasonClosedEvent,
+    SeasonCreatedEvent, VaultEvent, WithdrawEvent, WithdrawalRejectedEvent,
+    WithdrawalRequestedEvent,
+};
+use fees::{FeeConfig, HighWaterMark};
+use intents::IntentsDeposit;
+use leaderboard::{pnl_bps, Leaderboard, LeaderboardItem, DEFAULT_LEADERBOARD_LENGTH};
+use migration::VersionedCapitalAllocation;
+use pause::{PausableOperation, PauseStatus};
+use positions::{PositionChange, PositionChangeKind};
+use roles::Role;
+use seasons::Season;
+use shares::share_pool_key;
+use stats::AccountStats;
+use storage::AccountStorage;
+use token_receiver::DepositRejectReason;
+use tokens::{TokenConfig, NATIVE_NEAR_TOKEN_ID};
+use u256::U256;
+use withdrawals::WithdrawalRequest;
 
 type Balance = u128;
 type TwitterId = u128;
 
 const DEFAULT_LOCKTIME_IN_MS: u64 = 86_400_000;
+const DEFAULT_WITHDRAWAL_TIMEOUT_IN_MS: u64 = 259_200_000;
 const MAX_ASSETS_IN_CAPITAL_ALLOCATION: usize = 7;
-const GAS_FT_TRANSFER_CALL: Gas = Gas::from_tgas(25);
-const GAS_FT_TRANSFER: Gas = Gas::from_tgas(2);
-const GAS_WITHDRAW_CAPITAL: Gas = Gas::from_tgas(10);
+const MAX_FUNDING_SOURCES_IN_CAPITAL_ALLOCATION: usize = 10;
+const GAS_RESOLVE_WITHDRAW: Gas = Gas::from_tgas(5);
+const GAS_RESOLVE_WITHDRAW_CAPITAL: Gas = Gas::from_tgas(10);
+// position history and event of every landed leg in `on_withdraw_capital`
+const GAS_RESOLVE_WITHDRAW_CAPITAL_PER_LEG: Gas = Gas::from_tgas(3);
 const MIN_NEAR_DEPOSIT: NearToken = NearToken::from_millinear(10);
-const USDC_CONTRACT_ID: &str = "17208628f84f5d6ad33f0da3bbbeb27ffcb398eac501a31bd6ad2011e36133a1";
-const MIN_USDC_DEPOSIT: u128 = 100_000; // 0.1 USDC
-const INTENTS_CONTRACT_ID: &str = "intents.near";
+const USDC_DECIMALS: u8 = 6;
+const MIN_USDC_DEPOSIT: u128 = 100_000; // 0.1 USDC, default for the whitelisted USDC token
+
+// the code generated by `construct_uint!` trips clippy
+#[allow(clippy::assign_op_pattern)]
+mod u256 {
+    uint::construct_uint! {
+        pub struct U256(4);
+    }
+}
 
 #[ext_contract(ext_ft)]
 pub trait FungibleToken {
     fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
-    fn ft_transfer_call(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>, msg: String);
+    fn ft_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    );
 }
 
 #[near(serializers = [json, borsh])]
-#[derive(PanicOnDefault)]
+#[derive(PanicOnDefault, Clone)]
 pub struct AssetPosition {
     pub token_id: AccountId,
+    #[serde(with = "u128_dec_format")]
     pub amount: Balance,
 }
 
+// `u128` as a decimal string, JSON numbers lose precision above 2^53
+mod u128_dec_format {
+    use near_sdk::json_types::U128;
+    use near_sdk::serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S>(num: &u128, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        U128(*num).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<u128, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        U128::deserialize(deserializer).map(|num| num.0)
+    }
+}
+
 #[near(serializers = [json])]
 #[derive(PanicOnDefault)]
 pub struct AssetPositionOutput {
@@ -49,26 +127,86 @@ pub struct AssetPositionOutput {
     pub amount: U128,
 }
 
+impl From<&AssetPosition> for AssetPositionOutput {
+    fn from(position: &AssetPosition) -> Self {
+        Self {
+            token_id: position.token_id.clone(),
+            amount: U128::from(position.amount),
+        }
+    }
+}
 
-#[derive(BorshDeserialize, BorshSerialize, Serialize, NearSchema, PartialEq)]
+#[derive(BorshDeserialize, BorshSerialize, Serialize, NearSchema, PartialEq, Clone)]
 #[borsh(crate = "near_sdk::borsh")]
 #[serde(crate = "near_sdk::serde")]
 pub enum CapitalAllocationStatus {
     Active,
     Withdrawn,
+    // transfers to intents are in flight
+    Withdrawing,
+    // some positions landed, the rest can be retried
+    PartiallyWithdrawn,
+    // no position landed, all of them can be retried
+    Failed,
+}
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone, PartialEq)]
+pub enum EarlyExitReason {
+    StopLoss,
+    TakeProfit,
+    RiskLimit,
+    Rebalance,
+    Other,
+}
+
+// deposit debited to fund a Capital Allocation, credited back with its share of the exit value
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct FundingSource {
+    pub twitter_id: U128,
+    pub near_account_id: AccountId,
+    pub amount: U128,
 }
 
-#[derive(BorshDeserialize, BorshSerialize, Serialize, NearSchema)]
+#[derive(BorshDeserialize, BorshSerialize, Serialize, NearSchema, Clone)]
 #[borsh(crate = "near_sdk::borsh")]
 #[serde(crate = "near_sdk::serde")]
 pub struct CapitalAllocation {
     pub owner_id: AccountId,
     pub status: CapitalAllocationStatus,
     pub positions: Vec<AssetPosition>,
+    // amounts per token that were accepted by the intents contract
+    pub withdrawn: Vec<AssetPosition>,
     pub entry_timestamp: Timestamp,
     pub exit_timestamp: Timestamp,
     pub entry_value: AssetPosition,
     pub exit_value: Option<AssetPosition>,
+    // set when the agent exited before `exit_timestamp`
+    pub early_exit: Option<EarlyExitReason>,
+    // deposits of the entry value token which add up to the entry value
+    pub funding: Vec<FundingSource>,
+    // signed return in basis points, set with the exit value unless the entry value was empty
+    pub pnl_bps: Option<I128>,
+    // how intents.near credits the positions on withdrawal, the vault by default
+    pub intents_deposit: Option<IntentsDeposit>,
+    // `reduce_position` transfers awaiting their callback, the allocation can't be withdrawn meanwhile
+    pub pending_reductions: u32,
+}
+
+#[near(serializers = [json])]
+pub struct AllocationMaturity {
+    pub entry_timestamp: Timestamp,
+    pub exit_timestamp: Timestamp,
+    pub is_matured: bool,
+    pub remaining_ms: u64,
+    pub early_exit: Option<EarlyExitReason>,
+}
+
+#[near(serializers = [json])]
+pub struct WithdrawalLeg {
+    pub position_index: u32,
+    pub amount: U128,
 }
 
 #[derive(Deserialize, PanicOnDefault)]
@@ -78,7 +216,7 @@ pub struct CapitalAllocationInput {
     pub entry_value: AssetPosition,
 }
 
-#[derive(BorshDeserialize, BorshSerialize)]
+#[derive(BorshDeserialize, BorshSerialize, Clone, PartialEq)]
 #[borsh(crate = "near_sdk::borsh")]
 pub struct TwitterNearAccount {
     pub twitter_id: TwitterId,
@@ -92,78 +230,233 @@ pub struct Contract {
     agent_account_id: AccountId,
 
     locktime: u64,
+    // USDC and intents contract ids and gas for calls into them, see `config`
+    config: Config,
 
-    near_deposits: LookupMap<TwitterNearAccount, Balance>,
-    usdc_deposits: LookupMap<TwitterNearAccount, Balance>,
+    tokens: UnorderedMap<AccountId, TokenConfig>,
+    // tokens taken off the whitelist, balances in them can still be withdrawn
+    removed_tokens: UnorderedSet<AccountId>,
+    balances: LookupMap<(TwitterNearAccount, AccountId), Balance>,
 
     leaderboard: Leaderboard,
+    // number of items kept on each leaderboard
+    leaderboard_length: u32,
 
-    capital: LookupMap<u64, CapitalAllocation>,
+    capital: LookupMap<u64, VersionedCapitalAllocation>,
     next_capital_id: u64,
+    // ids of the allocations of every owner, in the order they were created
+    allocations_by_owner: LookupMap<AccountId, Vector<u64>>,
+    // ids of the allocations with `CapitalAllocationStatus::Active`
+    active_allocations: UnorderedSet<u64>,
+    // v0 allocations from `reindexed_allocations` up to `legacy_allocations` aren't indexed yet
+    legacy_allocations: u64,
+    reindexed_allocations: u64,
+    // positions added to and sent out of every allocation, see `positions`
+    position_history: LookupMap<u64, Vector<PositionChange>>,
+
+    storage_accounts: LookupMap<AccountId, AccountStorage>,
+    // bytes taken by a single storage registration
+    account_storage_usage: StorageUsage,
+    // bytes of the largest possible `balances` entry, reserved before a deposit creates one
+    ledger_entry_storage_usage: StorageUsage,
+
+    roles: UnorderedMap<AccountId, Vec<Role>>,
+    pause_status: PauseStatus,
+
+    // accounts a depositor allows NEAR withdrawals to be paid out to, besides themselves
+    withdrawal_recipients: LookupMap<AccountId, Vec<AccountId>>,
+    // requests by depositors, released to them without approval once `withdrawal_timeout` passes
+    withdrawal_requests: UnorderedMap<u64, WithdrawalRequest>,
+    // ids of the pending requests of every depositor
+    withdrawal_requests_by_account: LookupMap<AccountId, UnorderedSet<u64>>,
+    next_withdrawal_request_id: u64,
+    withdrawal_timeout: u64,
+
+    // set by a guardian, lets depositors pull their balances and stops the agent
+    emergency_mode: bool,
+
+    // NEP-141 claims on the share pool, see `shares`
+    shares: near_contract_standards::fungible_token::FungibleToken,
+    // entry value of unsettled allocations funded from the share pool
+    shares_allocated: Balance,
+
+    fees: FeeConfig,
+    // collects the fees, see `fees`
+    treasury_id: AccountId,
+    // fees by token, kept apart from the ledger so the treasury can also be a depositor
+    treasury_balances: UnorderedMap<AccountId, Balance>,
+    high_water_marks: LookupMap<(AccountId, AccountId), HighWaterMark>,
+
+    // competitions with their own leaderboards, indexed by season id
+    seasons: Vector<Season>,
+    open_seasons: Vec<u64>,
+
+    // aggregates of settled allocations by owner and token, see `stats`
+    account_stats: LookupMap<(AccountId, AccountId), AccountStats>,
+    // every account with a USDC return, paid by the owner like its stats
+    account_leaderboard: Leaderboard,
 }
 
 #[derive(BorshSerialize, BorshStorageKey)]
-#[borsh(crate = "near_sdk::borsh")]
+#[borsh(crate = "near_sdk::borsh", use_discriminant = true)]
 enum StorageKey {
-    NearDeposits,
-    UsdcDeposits,
-    CapitalAllocations,
+    // 0, 1 and 2 hold v0 deposits and allocations, see `migration`
+    Tokens = 3,
+    Balances = 4,
+    StorageAccounts = 5,
+    CapitalAllocations = 6,
+    Roles = 7,
+    WithdrawalRecipients = 8,
+    WithdrawalRequests = 9,
+    Shares = 10,
+    HighWaterMarks = 11,
+    Seasons = 12,
+    AccountStats = 13,
+    Leaderboard = 14,
+    AccountLeaderboard = 15,
+    // followed by the season id
+    SeasonLeaderboards = 16,
+    // followed by the hash of the owner id
+    AllocationsByOwner = 17,
+    ActiveAllocations = 18,
+    // followed by the allocation id
+    PositionHistory = 19,
+    // followed by the hash of the account id
+    WithdrawalRequestsByAccount = 20,
+    RemovedTokens = 21,
+    TreasuryBalances = 22,
 }
 
 #[near_bindgen]
 impl Contract {
     #[init]
-    pub fn new(agent: String, agent_account_id: AccountId, locktime: Option<u64>) -> Self {
+    pub fn new(
+        agent: String,
+        agent_account_id: AccountId,
+        treasury_id: AccountId,
+        locktime: Option<u64>,
+        config: Option<Config>,
+    ) -> Self {
         // time to lock capital in ms
         let locktime = locktime.unwrap_or(DEFAULT_LOCKTIME_IN_MS);
-        Self {
+        // network defaults come from the `mainnet` or `testnet` feature
+        let config = config.unwrap_or_default();
+        config.assert_valid();
+        let mut contract = Self {
             agent,
             agent_account_id,
 
             locktime,
+            config,
 
-            near_deposits: LookupMap::new(StorageKey::NearDeposits),
-            usdc_deposits: LookupMap::new(StorageKey::UsdcDeposits),
+            tokens: UnorderedMap::new(StorageKey::Tokens),
+            removed_tokens: UnorderedSet::new(StorageKey::RemovedTokens),
+            balances: LookupMap::new(StorageKey::Balances),
 
-            leaderboard: Leaderboard::default(),
+            leaderboard: Leaderboard::new(StorageKey::Leaderboard),
+            leaderboard_length: DEFAULT_LEADERBOARD_LENGTH,
 
             capital: LookupMap::new(StorageKey::CapitalAllocations),
             next_capital_id: 0,
-        }
+            allocations_by_owner: LookupMap::new(StorageKey::AllocationsByOwner),
+            active_allocations: UnorderedSet::new(StorageKey::ActiveAllocations),
+            legacy_allocations: 0,
+            reindexed_allocations: 0,
+            position_history: LookupMap::new(StorageKey::PositionHistory),
+
+            storage_accounts: LookupMap::new(StorageKey::StorageAccounts),
+            account_storage_usage: 0,
+            ledger_entry_storage_usage: 0,
+
+            roles: UnorderedMap::new(StorageKey::Roles),
+            pause_status: PauseStatus::default(),
+
+            withdrawal_recipients: LookupMap::new(StorageKey::WithdrawalRecipients),
+            withdrawal_requests: UnorderedMap::new(StorageKey::WithdrawalRequests),
+            withdrawal_requests_by_account: LookupMap::new(StorageKey::WithdrawalRequestsByAccount),
+            next_withdrawal_request_id: 0,
+            withdrawal_timeout: DEFAULT_WITHDRAWAL_TIMEOUT_IN_MS,
+
+            emergency_mode: false,
+
+            shares: near_contract_standards::fungible_token::FungibleToken::new(StorageKey::Shares),
+            shares_allocated: 0,
+
+            fees: FeeConfig::default(),
+            treasury_id,
+            treasury_balances: UnorderedMap::new(StorageKey::TreasuryBalances),
+            high_water_marks: LookupMap::new(StorageKey::HighWaterMarks),
+
+            seasons: Vector::new(StorageKey::Seasons),
+            open_seasons: vec![],
+
+            account_stats: LookupMap::new(StorageKey::AccountStats),
+            account_leaderboard: Leaderboard::new(StorageKey::AccountLeaderboard),
+        };
+        contract.measure_account_storage_usage();
+        contract.measure_ledger_entry_storage_usage();
+        contract.whitelist_default_tokens();
+        contract.internal_grant_role(&env::predecessor_account_id(), Role::Owner);
+        contract.internal_grant_role(&contract.agent_account_id.clone(), Role::Agent);
+        migration::write_state_version();
+
+        contract
     }
 
-    #[private]
     pub fn set_agent(&mut self, agent: String) {
+        self.require_role(Role::Owner);
         self.agent = agent;
     }
 
-    #[private]
+    // moves the Agent role over to the new account
     pub fn set_agent_account_id(&mut self, agent_account_id: AccountId) {
+        self.require_role(Role::Owner);
+        let previous_agent_account_id = self.agent_account_id.clone();
+        self.internal_revoke_role(&previous_agent_account_id, Role::Agent);
+        self.internal_grant_role(&agent_account_id, Role::Agent);
         self.agent_account_id = agent_account_id;
     }
 
-    #[private]
     pub fn set_locktime(&mut self, locktime: u64) {
+        self.require_role(Role::Owner);
         self.locktime = locktime;
     }
 
-    #[private]
-    // debug method to revert failed withdraw capital
-    pub fn set_allocation_status_to_active(&mut self, capital_id: u64) {
-        let mut capital = self
-            .capital
-            .get(&capital_id)
-            .expect("Capital Allocation not found");
-        capital.status = CapitalAllocationStatus::Active;
-        self.capital.insert(&capital_id, &capital);
+    // recovers an allocation whose `on_withdraw_capital` never completed, e.g. ran out of gas
+    // `landed` are the legs found in intents, they are recorded like in the callback and only
+    // the rest of the positions is sent again by the next `withdraw_capital`
+    pub fn set_allocation_status_to_failed(&mut self, capital_id: u64, landed: Vec<WithdrawalLeg>) {
+        self.require_role(Role::Owner);
+        let capital = self.internal_get_capital(capital_id);
+        assert!(
+            capital.status == CapitalAllocationStatus::Withdrawing,
+            "Capital Allocation is not withdrawing"
+        );
+        let used = landed
+            .into_iter()
+            .map(|leg| {
+                let position = capital
+                    .positions
+                    .get(leg.position_index as usize)
+                    .expect("Position not found");
+                assert!(
+                    leg.amount.0 <= position.amount,
+                    "Landed amount exceeds the position"
+                );
+                (leg.position_index, leg.amount.0)
+            })
+            .collect();
+        self.internal_resolve_withdrawal(capital_id, capital, used);
     }
 
     pub fn get_locktime(&self) -> u64 {
-        self.locktime.clone()
+        self.locktime
     }
 
     #[payable]
     pub fn deposit_near(&mut self, twitter_id: U128) {
+        self.assert_not_paused(PausableOperation::Deposits);
+        self.assert_not_emergency();
         let deposit = env::attached_deposit();
         let near_account_id = env::predecessor_account_id();
 
@@ -172,13 +465,6 @@ impl Contract {
             near_account_id: near_account_id.clone(),
         };
 
-        let balance = self.near_deposits.get(&key).unwrap_or(0);
-        let new_balance = balance + deposit.as_yoctonear();
-        assert!(
-            new_balance >= MIN_NEAR_DEPOSIT.as_yoctonear(),
-            "Deposit must be at least 0.01 NEAR"
-        );
-
         events::emit::run_agent(
             &self.agent,
             &serde_json::json!(
@@ -192,215 +478,463 @@ impl Contract {
             .to_string(),
         );
 
-        self.near_deposits.insert(&key, &new_balance);
+        let token_id = AccountId::from_str(NATIVE_NEAR_TOKEN_ID).unwrap();
+        self.internal_deposit(&key, &token_id, deposit.as_yoctonear());
+
+        VaultEvent::Deposit(DepositEvent {
+            account_id: near_account_id,
+            twitter_id,
+            token_id,
+            amount: U128::from(deposit.as_yoctonear()),
+            tweet_id: None,
+        })
+        .emit();
     }
 
     pub fn get_near_balance(&self, twitter_id: U128, near_account_id: AccountId) -> U128 {
-        if let Some(account) = self.near_deposits.get(&TwitterNearAccount {
-            twitter_id: twitter_id.0,
+        self.get_balance(
+            twitter_id,
             near_account_id,
-        }) {
-            U128::from(account)
-        } else {
-            U128(0)
-        }
+            AccountId::from_str(NATIVE_NEAR_TOKEN_ID).unwrap(),
+        )
     }
+
     pub fn get_usdc_balance(&self, twitter_id: U128, near_account_id: AccountId) -> U128 {
-        if let Some(account) = self.usdc_deposits.get(&TwitterNearAccount {
-            twitter_id: twitter_id.0,
-            near_account_id,
-        }) {
-            U128::from(account)
-        } else {
-            U128(0)
-        }
+        self.get_balance(twitter_id, near_account_id, self.usdc_contract_id())
     }
 
-    pub fn withdraw_near(&mut self, twitter_id: U128, near_account_id: AccountId) {
-        self.assert_agent();
+    // pays out to the depositor unless one of their allowed recipients is given
+    pub fn withdraw_near(
+        &mut self,
+        twitter_id: U128,
+        near_account_id: AccountId,
+        amount: Option<U128>,
+        receiver_id: Option<AccountId>,
+    ) {
+        self.require_role(Role::Operator);
+        self.assert_not_paused(PausableOperation::Withdrawals);
+        self.assert_not_emergency();
+        let receiver_id = receiver_id.unwrap_or(near_account_id.clone());
+        self.assert_withdrawal_recipient(&near_account_id, &receiver_id);
         let key = TwitterNearAccount {
             twitter_id: twitter_id.0,
             near_account_id,
         };
-        let balance = self
-            .near_deposits
-            .get(&key)
-            .expect("Account not found");
+        self.assert_depositor_key(&key);
+        let token_id = AccountId::from_str(NATIVE_NEAR_TOKEN_ID).unwrap();
+        let balance = self.internal_get_balance(&key, &token_id);
         assert!(balance > 0, "No balance to withdraw");
-        self.near_deposits.insert(&key, &0);
-        Promise::new(self.agent_account_id.clone()).transfer(NearToken::from_yoctonear(balance));
+        let amount = amount.unwrap_or(U128::from(balance));
+        self.internal_withdraw(&key, &token_id, amount.0);
+        self.internal_payout(key, token_id, amount, receiver_id);
     }
 
-    pub fn withdraw_usdc(&mut self, twitter_id: U128, near_account_id: AccountId, amount: Option<U128>) {
-        self.assert_agent();
+    #[private]
+    // re-credits the balance if the transfer failed, e.g. the receiver doesn't exist
+    pub fn on_withdraw_near(
+        &mut self,
+        twitter_id: U128,
+        near_account_id: AccountId,
+        amount: U128,
+        receiver_id: AccountId,
+    ) -> bool {
+        let success = is_promise_success();
+        let token_id = AccountId::from_str(NATIVE_NEAR_TOKEN_ID).unwrap();
+
+        if !success {
+            let key = TwitterNearAccount {
+                twitter_id: twitter_id.0,
+                near_account_id: near_account_id.clone(),
+            };
+            // the vault restores the entry if the account unregistered after the payout
+            self.internal_credit_balance(&key, &token_id, amount.0, &env::current_account_id());
+        }
+
+        VaultEvent::Withdraw(WithdrawEvent {
+            account_id: near_account_id,
+            twitter_id,
+            token_id,
+            amount,
+            receiver_id,
+            success,
+        })
+        .emit();
+
+        success
+    }
+
+    pub fn withdraw_usdc(
+        &mut self,
+        twitter_id: U128,
+        near_account_id: AccountId,
+        amount: Option<U128>,
+        receiver_id: Option<AccountId>,
+    ) {
+        self.withdraw_ft(
+            twitter_id,
+            near_account_id,
+            self.usdc_contract_id(),
+            amount,
+            receiver_id,
+        );
+    }
+
+    pub fn withdraw_ft(
+        &mut self,
+        twitter_id: U128,
+        near_account_id: AccountId,
+        token_id: AccountId,
+        amount: Option<U128>,
+        receiver_id: Option<AccountId>,
+    ) {
+        self.require_role(Role::Operator);
+        self.assert_not_paused(PausableOperation::Withdrawals);
+        self.assert_not_emergency();
+        assert_ne!(
+            token_id.as_str(),
+            NATIVE_NEAR_TOKEN_ID,
+            "Use withdraw_near for NEAR"
+        );
+        let receiver_id = receiver_id.unwrap_or(near_account_id.clone());
+        self.assert_withdrawal_recipient(&near_account_id, &receiver_id);
         let key = TwitterNearAccount {
             twitter_id: twitter_id.0,
             near_account_id,
         };
-        let balance = self
-            .usdc_deposits
-            .get(&key)
-            .expect("Twitter account not found");
+        self.assert_depositor_key(&key);
+        let balance = self.internal_get_balance(&key, &token_id);
         assert!(balance > 0, "No balance to withdraw");
         let amount = amount.unwrap_or(U128::from(balance));
-        assert!(balance >= amount.0, "Not enough balance to withdraw");
-        self.usdc_deposits.insert(&key, &(balance - amount.0));
-
-        ext_ft::ext(AccountId::from_str(USDC_CONTRACT_ID).unwrap())
-            .with_static_gas(GAS_FT_TRANSFER)
-            .with_attached_deposit(NearToken::from_yoctonear(1))
-            .ft_transfer(self.agent_account_id.clone(), U128::from(amount.0), None);
+        self.internal_withdraw(&key, &token_id, amount.0);
+        self.internal_payout(key, token_id, amount, receiver_id);
     }
 
-    pub fn get_capital_allocation(
-        &self,
-        capital_id: u64,
-    ) -> (bool, AccountId, Timestamp, Vec<AssetPositionOutput>) {
-        let capital = self
-            .capital
-            .get(&capital_id)
-            .expect("Capital Allocation not found");
+    #[private]
+    // re-credits the balance if the token contract rejected the transfer
+    pub fn on_withdraw_ft(
+        &mut self,
+        twitter_id: U128,
+        near_account_id: AccountId,
+        token_id: AccountId,
+        amount: U128,
+        receiver_id: AccountId,
+    ) -> bool {
+        let success = is_promise_success();
+
+        if !success {
+            let key = TwitterNearAccount {
+                twitter_id: twitter_id.0,
+                near_account_id: near_account_id.clone(),
+            };
+            // the vault restores the entry if the account unregistered after the payout
+            self.internal_credit_balance(&key, &token_id, amount.0, &env::current_account_id());
+        }
 
-        (
-            capital.status == CapitalAllocationStatus::Active,
-            capital.owner_id,
-            capital.exit_timestamp,
-            capital.positions.iter().map(|p| AssetPositionOutput {
-                token_id: p.token_id.clone(),
-                amount: U128::from(p.amount),
-            }).collect(),
-        )
+        VaultEvent::Withdraw(WithdrawEvent {
+            account_id: near_account_id,
+            twitter_id,
+            token_id,
+            amount,
+            receiver_id,
+            success,
+        })
+        .emit();
+
+        success
     }
 
-    pub fn create_capital_allocation(&mut self, owner_id: AccountId, entry_amount: U128, entry_token_id: Option<AccountId>) -> u64 {
-        self.assert_agent();
+    pub fn create_capital_allocation(
+        &mut self,
+        owner_id: AccountId,
+        funding: Vec<FundingSource>,
+        entry_token_id: Option<AccountId>,
+        locktime: Option<u64>,
+    ) -> u64 {
+        self.require_role(Role::Agent);
+        self.assert_not_paused(PausableOperation::Allocations);
+        self.assert_not_emergency();
+
+        // time to lock capital in ms, falls back to the contract-wide locktime
+        let locktime = locktime.unwrap_or(self.locktime);
+        let entry_token_id = entry_token_id.unwrap_or(self.usdc_contract_id());
+        let entry_amount = self.internal_debit_funding(&funding, &entry_token_id);
 
         let capital = CapitalAllocation {
             owner_id,
             status: CapitalAllocationStatus::Active,
             positions: vec![],
+            withdrawn: vec![],
             entry_timestamp: env::block_timestamp_ms(),
-            exit_timestamp: env::block_timestamp_ms() + DEFAULT_LOCKTIME_IN_MS,
+            exit_timestamp: env::block_timestamp_ms() + locktime,
             entry_value: AssetPosition {
-                token_id: entry_token_id.unwrap_or(AccountId::from_str(USDC_CONTRACT_ID).unwrap()),
-                amount: entry_amount.0,
+                token_id: entry_token_id,
+                amount: entry_amount,
             },
             exit_value: None,
+            early_exit: None,
+            funding,
+            pnl_bps: None,
+            intents_deposit: None,
+            pending_reductions: 0,
         };
 
+        let owner_id = capital.owner_id.clone();
         let capital_id = self.next_capital_id;
-        self.capital.insert(&capital_id, &capital);
+        let initial_storage = env::storage_usage();
+        self.internal_set_capital(capital_id, &capital);
+        self.internal_index_owner_allocation(&owner_id, capital_id);
+        self.internal_track_storage(&owner_id, initial_storage);
+        self.assert_storage_covered(&owner_id);
         self.next_capital_id += 1;
 
+        VaultEvent::AllocationCreated(AllocationCreatedEvent {
+            capital_id,
+            owner_id,
+            entry_value: AssetPositionOutput::from(&capital.entry_value),
+            entry_timestamp: capital.entry_timestamp,
+            exit_timestamp: capital.exit_timestamp,
+            funding: capital.funding.clone(),
+        })
+        .emit();
+
         capital_id
     }
 
-    pub fn withdraw_capital(&mut self, capital_id: u64) {
-        self.assert_agent();
+    pub fn get_allocation_maturity(&self, capital_id: u64) -> AllocationMaturity {
+        let capital = self.internal_get_capital(capital_id);
+        let now = env::block_timestamp_ms();
 
-        let mut capital = self
-            .capital
-            .get(&capital_id)
-            .expect("Capital Allocation not found");
-        assert!(
-            capital.status == CapitalAllocationStatus::Active,
-            "Capital Allocation already withdrawn"
-        );
+        AllocationMaturity {
+            entry_timestamp: capital.entry_timestamp,
+            exit_timestamp: capital.exit_timestamp,
+            is_matured: capital.is_matured(now),
+            remaining_ms: capital.exit_timestamp.saturating_sub(now),
+            early_exit: capital.early_exit,
+        }
+    }
+
+    pub fn withdraw_capital(&mut self, capital_id: u64) {
+        self.require_role(Role::Operator);
+        self.assert_not_paused(PausableOperation::CapitalWithdrawals);
+        self.assert_not_emergency();
+
+        let capital = self.internal_get_capital(capital_id);
+        // the position history of the landed legs is charged to the owner in the callback
+        self.assert_storage_covered(&capital.storage_payer_id());
+        // retries of an early exit don't have to wait for maturity
         assert!(
-            capital.exit_timestamp >= env::block_timestamp_ms(),
+            capital.early_exit.is_some() || capital.is_matured(env::block_timestamp_ms()),
             "Capital Allocation not yet matured"
         );
 
-        capital.status = CapitalAllocationStatus::Withdrawn;
-        self.capital.insert(&capital_id, &capital);
+        self.internal_withdraw_capital(capital_id, capital);
+    }
+
+    // withdraws capital before `exit_timestamp`, the reason is kept on the allocation
+    pub fn exit_capital_early(&mut self, capital_id: u64, reason: EarlyExitReason) {
+        self.require_role(Role::Agent);
+        self.assert_not_paused(PausableOperation::CapitalWithdrawals);
+        self.assert_not_emergency();
 
-        let gas_to_spend =
-            GAS_WITHDRAW_CAPITAL.as_gas() + GAS_FT_TRANSFER.as_gas() * capital.positions.len() as u64;
+        let mut capital = self.internal_get_capital(capital_id);
+        self.assert_storage_covered(&capital.storage_payer_id());
         assert!(
-            env::prepaid_gas().as_gas() >= gas_to_spend,
-            "Not enough gas to withdraw capital"
+            !capital.is_matured(env::block_timestamp_ms()),
+            "Capital Allocation already matured"
         );
 
-        for position in capital.positions.iter() {
-            ext_ft::ext(position.token_id.clone())
-                .with_static_gas(GAS_FT_TRANSFER_CALL)
-                .with_attached_deposit(NearToken::from_yoctonear(1))
-                .ft_transfer_call(
-                    AccountId::from_str(INTENTS_CONTRACT_ID).unwrap(),
-                    U128::from(position.amount),
-                    None,
-                    "".to_string()
-                );
-        }
+        capital.early_exit = Some(reason);
+        self.internal_withdraw_capital(capital_id, capital);
     }
 
-    pub fn set_capital_exit_value(&mut self, capital_id: u64, exit_amount: U128, exit_token_id: Option<AccountId>) {
-        let exit_token_id = exit_token_id.unwrap_or(AccountId::from_str(USDC_CONTRACT_ID).unwrap());
+    #[private]
+    // records which legs landed in intents, one promise result per leg
+    pub fn on_withdraw_capital(
+        &mut self,
+        capital_id: u64,
+        legs: Vec<WithdrawalLeg>,
+    ) -> CapitalAllocationStatus {
+        assert_eq!(
+            env::promise_results_count(),
+            legs.len() as u64,
+            "Expected one promise result per withdrawal leg"
+        );
+
+        let capital = self.internal_get_capital(capital_id);
+        let used = legs
+            .iter()
+            .enumerate()
+            .map(|(result_index, leg)| {
+                // ft_transfer_call resolves to the amount the receiver kept, the rest was refunded
+                let used = match env::promise_result(result_index as u64) {
+                    PromiseResult::Successful(value) => serde_json::from_slice::<U128>(&value)
+                        .map(|used| used.0.min(leg.amount.0))
+                        .unwrap_or(0),
+                    PromiseResult::Failed => 0,
+                };
+                (leg.position_index, used)
+            })
+            .collect();
+        self.internal_resolve_withdrawal(capital_id, capital, used)
+    }
+    pub fn set_capital_exit_value(
+        &mut self,
+        capital_id: u64,
+        exit_amount: U128,
+        exit_token_id: Option<AccountId>,
+    ) {
+        let exit_token_id = exit_token_id.unwrap_or(self.usdc_contract_id());
 
-        self.assert_agent();
-        let mut capital = self
-            .capital
-            .get(&capital_id)
-            .expect("Capital Allocation not found");
+        self.require_role(Role::Operator);
+        self.assert_not_paused(PausableOperation::ExitValues);
+        self.assert_not_emergency();
+        let mut capital = self.internal_get_capital(capital_id);
 
         assert!(
             capital.status == CapitalAllocationStatus::Withdrawn,
             "Capital Allocation was not withdrawn"
         );
-
+        assert!(
+            capital.exit_value.is_none(),
+            "Capital Allocation exit value already set"
+        );
+        assert_eq!(
+            capital.pending_reductions, 0,
+            "Capital Allocation has pending position reductions"
+        );
 
         assert_eq!(
             capital.entry_value.token_id, exit_token_id,
             "Exit value token must match entry value token"
         );
 
-        // calculate profit/loss in percents as u128, store profit: bool, percent: u128
-        let if_profit = exit_amount.0 >= capital.entry_value.amount;
-        let profit_loss_in_percent = if if_profit {
-            (exit_amount.0 - capital.entry_value.amount) * 100 / capital.entry_value.amount
-        } else {
-            (capital.entry_value.amount - exit_amount.0) * 100 / capital.entry_value.amount
-        };
-
-        self.leaderboard.add_item(
-            LeaderboardItem {
-                near_account_id: capital.owner_id.clone(),
-                value: profit_loss_in_percent,
-                capital_id,
-            },
-            if_profit,
-        );
+        capital.pnl_bps = pnl_bps(capital.entry_value.amount, exit_amount.0).map(I128::from);
+        let ranked = capital.pnl_bps.is_some_and(|pnl_bps| {
+            self.leaderboard.add_item(
+                LeaderboardItem {
+                    near_account_id: capital.owner_id.clone(),
+                    pnl_bps,
+                    capital_id,
+                },
+                self.leaderboard_length,
+            )
+        });
 
         capital.exit_value = Some(AssetPosition {
             token_id: exit_token_id,
             amount: exit_amount.0,
         });
 
-        self.capital.insert(&capital_id, &capital);
+        let initial_storage = env::storage_usage();
+        self.internal_set_capital(capital_id, &capital);
+        self.internal_track_storage(&capital.storage_payer_id(), initial_storage);
+        self.internal_add_to_seasons(capital_id, &capital);
+        self.internal_update_account_stats(capital_id, &capital);
+        let fees = self.internal_charge_fees(capital_id, &capital);
+        self.internal_credit_proceeds(&capital, exit_amount.0 - fees);
+        // stats, the high water mark and proceeds of unregistered depositors are the owner's
+        self.assert_storage_covered(&capital.storage_payer_id());
+
+        VaultEvent::ExitValueSet(ExitValueSetEvent {
+            capital_id,
+            owner_id: capital.owner_id.clone(),
+            entry_value: AssetPositionOutput::from(&capital.entry_value),
+            exit_value: AssetPositionOutput::from(capital.exit_value.as_ref().unwrap()),
+            pnl_bps: capital.pnl_bps,
+        })
+        .emit();
+
+        if ranked {
+            VaultEvent::LeaderboardUpdated(LeaderboardUpdatedEvent {
+                capital_id,
+                near_account_id: capital.owner_id,
+                pnl_bps: capital.pnl_bps.unwrap(),
+            })
+            .emit();
+        }
     }
 
-    pub fn get_leaderboard(&self) -> (Vec<LeaderboardItem>, Vec<LeaderboardItem>) {
-        (self.leaderboard.profit.clone(), self.leaderboard.loss.clone())
+    pub fn get_leaderboard(&self) -> Vec<LeaderboardItem> {
+        self.leaderboard.to_vec()
     }
 
     pub fn get_capital(&self, capital_id: u64) -> CapitalAllocation {
-        self.capital.get(&capital_id).expect("Capital Allocation not found")
+        self.internal_get_capital(capital_id)
+    }
+}
+
+impl CapitalAllocation {
+    pub fn is_matured(&self, now: Timestamp) -> bool {
+        now >= self.exit_timestamp
     }
+
+    // account charged for the storage of the allocation and its history
+    // v0 allocations, the ones without funding, were never charged so the vault keeps paying for them
+    pub(crate) fn storage_payer_id(&self) -> AccountId {
+        if self.funding.is_empty() {
+            env::current_account_id()
+        } else {
+            self.owner_id.clone()
+        }
+    }
+}
+
+// basis points in a whole
+const BPS_DENOMINATOR: u128 = 10_000;
+
+// `a * b / c` without overflowing in between
+fn mul_div(a: u128, b: u128, c: u128) -> u128 {
+    (U256::from(a) * U256::from(b) / U256::from(c)).as_u128()
+}
+
+// page size of list views, which have to fit in view gas
+const DEFAULT_PAGE_LIMIT: u64 = 20;
+const MAX_PAGE_LIMIT: u64 = 100;
+
+fn page_limit(limit: Option<u64>) -> u64 {
+    limit.unwrap_or(DEFAULT_PAGE_LIMIT).min(MAX_PAGE_LIMIT)
+}
+
+fn is_promise_success() -> bool {
+    assert_eq!(
+        env::promise_results_count(),
+        1,
+        "Expected exactly one promise result"
+    );
+    matches!(env::promise_result(0), PromiseResult::Successful(_))
 }
 
 impl Contract {
-    fn assert_agent(&self) {
-        assert_eq!(
-            env::predecessor_account_id(),
-            self.agent_account_id,
-            "Only agent can call this method"
+    // the share pool has its own exit, `redeem_shares`
+    fn assert_depositor_key(&self, key: &TwitterNearAccount) {
+        assert!(
+            *key != share_pool_key(),
+            "Can't withdraw the share pool balance"
+        );
+    }
+
+    fn whitelist_default_tokens(&mut self) {
+        self.tokens.insert(
+            &AccountId::from_str(NATIVE_NEAR_TOKEN_ID).unwrap(),
+            &TokenConfig {
+                decimals: 24,
+                min_deposit: U128::from(MIN_NEAR_DEPOSIT.as_yoctonear()),
+            },
+        );
+        self.tokens.insert(
+            &self.usdc_contract_id(),
+            &TokenConfig {
+                decimals: USDC_DECIMALS,
+                min_deposit: U128::from(MIN_USDC_DEPOSIT),
+            },
         );
     }
 
-    pub fn deposit_usdc(
+    pub fn deposit_ft(
         &mut self,
         twitter_id: U128,
         near_account_id: AccountId,
+        token_id: AccountId,
         amount: u128,
         input_tweet_id: Option<U128>,
     ) {
@@ -409,21 +943,14 @@ impl Contract {
             near_account_id: near_account_id.clone(),
         };
 
-        let balance = self.usdc_deposits.get(&key).unwrap_or(0);
-
-        let new_balance = balance + amount;
-        assert!(
-            new_balance >= MIN_USDC_DEPOSIT,
-            "Deposit must be at least 0.1 USDC"
-        );
-
         events::emit::run_agent(
             &self.agent,
             &serde_json::json!(
                 {
-                    "action": "deposit_usdc".to_string(),
+                    "action": "deposit_ft".to_string(),
                     "account_id": near_account_id,
                     "twitter_id": twitter_id,
+                    "token_id": token_id,
                     "deposit": amount.to_string(),
                     "tweet_id": input_tweet_id.unwrap_or(U128(0)),
                 }
@@ -431,19 +958,243 @@ impl Contract {
             .to_string(),
         );
 
-        self.usdc_deposits.insert(&key, &new_balance);
+        self.internal_deposit(&key, &token_id, amount);
+
+        VaultEvent::Deposit(DepositEvent {
+            account_id: near_account_id,
+            twitter_id,
+            token_id,
+            amount: U128::from(amount),
+            tweet_id: input_tweet_id,
+        })
+        .emit();
     }
 
-    fn add_position(&mut self, capital_id: u64, token_id: AccountId, amount: Balance) {
-        let mut capital = self
-            .capital
-            .get(&capital_id)
-            .expect("Capital Allocation not found");
+    fn internal_withdraw_capital(&mut self, capital_id: u64, mut capital: CapitalAllocation) {
+        assert!(
+            matches!(
+                capital.status,
+                CapitalAllocationStatus::Active
+                    | CapitalAllocationStatus::PartiallyWithdrawn
+                    | CapitalAllocationStatus::Failed
+            ),
+            "Capital Allocation already withdrawn"
+        );
+        // a refund of a reduction has to land on the positions before they are sent out
+        assert_eq!(
+            capital.pending_reductions, 0,
+            "Capital Allocation has pending position reductions"
+        );
+
+        // only legs which haven't landed yet are sent, so a retry skips settled positions
+        let legs: Vec<WithdrawalLeg> = capital
+            .positions
+            .iter()
+            .enumerate()
+            .filter(|(_, position)| position.amount > 0)
+            .map(|(index, position)| WithdrawalLeg {
+                position_index: index as u32,
+                amount: U128::from(position.amount),
+            })
+            .collect();
+
+        if legs.is_empty() {
+            capital.status = CapitalAllocationStatus::Withdrawn;
+            self.internal_set_capital(capital_id, &capital);
+            VaultEvent::CapitalWithdrawn(CapitalWithdrawnEvent {
+                capital_id,
+                owner_id: capital.owner_id,
+                status: capital.status,
+                withdrawn: vec![],
+            })
+            .emit();
+            return;
+        }
+
+        let gas_to_resolve = Gas::from_gas(
+            GAS_RESOLVE_WITHDRAW_CAPITAL.as_gas()
+                + GAS_RESOLVE_WITHDRAW_CAPITAL_PER_LEG.as_gas() * legs.len() as u64,
+        );
+        let gas_to_spend = self.config.gas_withdraw_capital.as_gas()
+            + gas_to_resolve.as_gas()
+            + self.config.gas_ft_transfer_call.as_gas() * legs.len() as u64;
         assert!(
-            capital.positions.len() < MAX_ASSETS_IN_CAPITAL_ALLOCATION,
-            "Too many assets in Capital Allocation"
+            env::prepaid_gas().as_gas() >= gas_to_spend,
+            "Not enough gas to withdraw capital"
         );
 
+        capital.status = CapitalAllocationStatus::Withdrawing;
+        self.internal_set_capital(capital_id, &capital);
+
+        let transfers = legs
+            .iter()
+            .map(|leg| {
+                let token_id = &capital.positions[leg.position_index as usize].token_id;
+                ext_ft::ext(token_id.clone())
+                    .with_static_gas(self.config.gas_ft_transfer_call)
+                    .with_attached_deposit(NearToken::from_yoctonear(1))
+                    .ft_transfer_call(
+                        self.config.intents_contract_id.clone(),
+                        leg.amount,
+                        None,
+                        capital.intents_msg(token_id),
+                    )
+            })
+            .reduce(|joint, transfer| joint.and(transfer))
+            .unwrap();
+
+        transfers.then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(gas_to_resolve)
+                .on_withdraw_capital(capital_id, legs),
+        );
+    }
+
+    // records what landed in intents per position and settles the status of the withdrawal
+    fn internal_resolve_withdrawal(
+        &mut self,
+        capital_id: u64,
+        mut capital: CapitalAllocation,
+        used: Vec<(u32, Balance)>,
+    ) -> CapitalAllocationStatus {
+        let mut landed: Vec<AssetPositionOutput> = vec![];
+
+        for (position_index, used) in used {
+            if used == 0 {
+                continue;
+            }
+
+            let position = &mut capital.positions[position_index as usize];
+            position.amount -= used;
+            let token_id = position.token_id.clone();
+            landed.push(AssetPositionOutput {
+                token_id: token_id.clone(),
+                amount: U128::from(used),
+            });
+            self.internal_record_position_change(
+                capital_id,
+                &capital.storage_payer_id(),
+                PositionChangeKind::Withdrawn,
+                token_id.clone(),
+                used,
+            );
+            match capital
+                .withdrawn
+                .iter_mut()
+                .find(|w| w.token_id == token_id)
+            {
+                Some(withdrawn) => withdrawn.amount += used,
+                None => capital.withdrawn.push(AssetPosition {
+                    token_id,
+                    amount: used,
+                }),
+            }
+        }
+
+        capital.status = if capital.positions.iter().all(|p| p.amount == 0) {
+            CapitalAllocationStatus::Withdrawn
+        } else if !capital.withdrawn.is_empty() {
+            CapitalAllocationStatus::PartiallyWithdrawn
+        } else {
+            CapitalAllocationStatus::Failed
+        };
+        let initial_storage = env::storage_usage();
+        self.internal_set_capital(capital_id, &capital);
+        self.internal_track_storage(&capital.storage_payer_id(), initial_storage);
+
+        VaultEvent::CapitalWithdrawn(CapitalWithdrawnEvent {
+            capital_id,
+            owner_id: capital.owner_id,
+            status: capital.status.clone(),
+            withdrawn: landed,
+        })
+        .emit();
+
+        capital.status
+    }
+
+    // debits all sources before the allocation is stored, any shortfall reverts the whole call
+    fn internal_debit_funding(
+        &mut self,
+        funding: &[FundingSource],
+        token_id: &AccountId,
+    ) -> Balance {
+        assert!(!funding.is_empty(), "Capital Allocation must be funded");
+        assert!(
+            funding.len() <= MAX_FUNDING_SOURCES_IN_CAPITAL_ALLOCATION,
+            "Too many funding sources in Capital Allocation"
+        );
+
+        let mut entry_amount: Balance = 0;
+        for source in funding {
+            assert!(source.amount.0 > 0, "Funding amount must be positive");
+            let key = TwitterNearAccount {
+                twitter_id: source.twitter_id.0,
+                near_account_id: source.near_account_id.clone(),
+            };
+            self.internal_withdraw(&key, token_id, source.amount.0);
+            if key == share_pool_key() {
+                assert!(
+                    *token_id == self.share_token_id(),
+                    "Share pool can only fund allocations in {}",
+                    self.share_token_id()
+                );
+                self.shares_allocated += source.amount.0;
+            }
+            entry_amount += source.amount.0;
+        }
+        entry_amount
+    }
+
+    // splits the exit value net of fees pro-rata to the funding, the rounding remainder goes to the last source
+    fn internal_credit_proceeds(&mut self, capital: &CapitalAllocation, proceeds_amount: Balance) {
+        let exit_value = capital.exit_value.as_ref().unwrap();
+        // v0 allocations weren't funded from deposits, the owner is credited under twitter id 0
+        if capital.funding.is_empty() {
+            let key = TwitterNearAccount {
+                twitter_id: 0,
+                near_account_id: capital.owner_id.clone(),
+            };
+            // v0 didn't charge storage, the vault keeps paying for its owners
+            self.internal_credit_balance(
+                &key,
+                &exit_value.token_id,
+                proceeds_amount,
+                &env::current_account_id(),
+            );
+            return;
+        }
+        let mut remaining = proceeds_amount;
+        for (index, source) in capital.funding.iter().enumerate() {
+            let proceeds = if index + 1 == capital.funding.len() {
+                remaining
+            } else {
+                mul_div(proceeds_amount, source.amount.0, capital.entry_value.amount)
+            };
+            remaining -= proceeds;
+
+            let key = TwitterNearAccount {
+                twitter_id: source.twitter_id.0,
+                near_account_id: source.near_account_id.clone(),
+            };
+            // the settled exit value replaces the entry value in the share NAV
+            if key == share_pool_key() {
+                self.shares_allocated -= source.amount.0;
+            }
+            if proceeds == 0 {
+                continue;
+            }
+            // the owner pays for depositors that unregistered while their funds were allocated
+            self.internal_credit_balance(&key, &exit_value.token_id, proceeds, &capital.owner_id);
+        }
+    }
+
+    fn add_position(&mut self, capital_id: u64, token_id: AccountId, amount: Balance) {
+        let mut capital = self.internal_get_capital(capital_id);
+        assert!(
+            capital.status == CapitalAllocationStatus::Active,
+            "Capital Allocation is not active"
+        );
         // skip running agent on `add_position` because it doesn't handle this action
         // events::emit::run_agent(
         //     &self.agent,
@@ -458,39 +1209,77 @@ impl Contract {
         //     .to_string(),
         // );
 
-        let position = AssetPosition { token_id, amount };
-
-        capital.positions.push(position);
+        // repeated transfers of a token top up its position
+        match capital
+            .positions
+            .iter_mut()
+            .find(|p| p.token_id == token_id)
+        {
+            Some(position) => position.amount += amount,
+            None => {
+                assert!(
+                    capital.positions.len() < MAX_ASSETS_IN_CAPITAL_ALLOCATION,
+                    "Too many assets in Capital Allocation"
+                );
+                capital.positions.push(AssetPosition {
+                    token_id: token_id.clone(),
+                    amount,
+                });
+            }
+        }
 
-        self.capital.insert(&capital_id, &capital);
+        VaultEvent::PositionAdded(PositionAddedEvent {
+            capital_id,
+            token_id: token_id.clone(),
+            amount: U128::from(amount),
+        })
+        .emit();
+
+        let initial_storage = env::storage_usage();
+        self.internal_set_capital(capital_id, &capital);
+        self.internal_track_storage(&capital.storage_payer_id(), initial_storage);
+        self.internal_record_position_change(
+            capital_id,
+            &capital.storage_payer_id(),
+            PositionChangeKind::Added,
+            token_id,
+            amount,
+        );
+        // panicking refunds the transfer
+        self.assert_storage_covered(&capital.storage_payer_id());
     }
 }
 
-
 #[cfg(all(test, not(target_arch = "wasm32")))]
 mod tests {
     #[allow(unused_imports)]
     use near_contract_standards::fungible_token::{metadata::FT_METADATA_SPEC, Balance};
-    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::test_utils::accounts;
     use near_sdk::testing_env;
 
     use super::*;
-
-    fn get_context(predecessor_account_id: AccountId) -> VMContextBuilder {
-        let mut builder = VMContextBuilder::new();
-        builder
-            .current_account_id(accounts(0))
-            .signer_account_id(predecessor_account_id.clone())
-            .predecessor_account_id(predecessor_account_id);
-        builder
-    }
+    use crate::test_utils::{
+        fund_allocation, fund_allocation_with, get_context, set_promise_results,
+    };
 
     fn get_contract() -> Contract {
-        Contract::new(
+        let mut contract = Contract::new(
             "agent.near".to_string(),
-            accounts(1).into(),
+            accounts(1),
+            accounts(0),
             None,
-        )
+            None,
+        );
+        // storage for the depositors and allocation owners used below
+        contract.internal_storage_deposit(&accounts(1), NearToken::from_near(1).as_yoctonear());
+        contract.internal_storage_deposit(&accounts(3), NearToken::from_near(1).as_yoctonear());
+        // accounts(1) is the owner and the agent, let it settle allocations too
+        contract.internal_grant_role(&accounts(1), Role::Operator);
+        contract
+    }
+
+    fn usdc() -> AccountId {
+        AccountId::from_str(DEFAULT_USDC_CONTRACT_ID).unwrap()
     }
 
     #[test]
@@ -498,16 +1287,13 @@ mod tests {
         let context = get_context(accounts(1));
         testing_env!(context.build());
         let contract = get_contract();
-        assert!(contract.leaderboard.profit.is_empty());
-        assert!(contract.leaderboard.loss.is_empty());
+        assert!(contract.get_leaderboard().is_empty());
     }
 
     #[test]
     fn test_deposit_near() {
         let mut context = get_context(accounts(1));
-        testing_env!(context
-            .attached_deposit(NearToken::from_near(1))
-            .build());
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
         let mut contract = get_contract();
 
         let twitter_id = U128(1845765845647056907);
@@ -517,16 +1303,26 @@ mod tests {
     }
 
     #[test]
-    fn test_withdraw_near() {
+    #[should_panic(expected = "Deposit must be at least")]
+    fn test_deposit_near_below_minimum() {
         let mut context = get_context(accounts(1));
         testing_env!(context
-            .attached_deposit(NearToken::from_near(1))
+            .attached_deposit(NearToken::from_millinear(1))
             .build());
         let mut contract = get_contract();
 
+        contract.deposit_near(U128(1845765845647056907));
+    }
+
+    #[test]
+    fn test_withdraw_near() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let mut contract = get_contract();
+
         let twitter_id = U128(1845765845647056907);
         contract.deposit_near(twitter_id);
-        contract.withdraw_near(twitter_id, accounts(1));
+        contract.withdraw_near(twitter_id, accounts(1), None, None);
         let balance = contract.get_near_balance(twitter_id, accounts(1));
         assert_eq!(balance.0, 0);
     }
@@ -540,35 +1336,496 @@ mod tests {
         let deposit = NearToken::from_millinear(567).as_yoctonear();
 
         let twitter_id = U128(1845765845647056907);
-        contract.deposit_usdc(twitter_id, accounts(3), deposit, None);
+        contract.deposit_ft(twitter_id, accounts(3), usdc(), deposit, None);
         let balance = contract.get_usdc_balance(twitter_id, accounts(3));
         assert_eq!(balance.0, deposit);
     }
 
+    #[test]
+    fn test_withdraw_usdc() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+
+        let twitter_id = U128(1845765845647056907);
+        contract.deposit_ft(twitter_id, accounts(3), usdc(), 1_000_000, None);
+        contract.withdraw_usdc(twitter_id, accounts(3), Some(U128(400_000)), None);
+        let balance = contract.get_usdc_balance(twitter_id, accounts(3));
+        assert_eq!(balance.0, 600_000);
+    }
+
+    #[test]
+    fn test_withdraw_usdc_to_allowed_recipient() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+        testing_env!(get_context(accounts(3)).build());
+        contract.add_withdrawal_recipient(accounts(4));
+
+        testing_env!(get_context(accounts(1)).build());
+        let twitter_id = U128(1845765845647056907);
+        contract.deposit_ft(twitter_id, accounts(3), usdc(), 1_000_000, None);
+        contract.withdraw_usdc(twitter_id, accounts(3), None, Some(accounts(4)));
+        assert_eq!(contract.get_usdc_balance(twitter_id, accounts(3)).0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Receiver is not an allowed withdrawal recipient")]
+    fn test_withdraw_usdc_to_agent_account() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+
+        let twitter_id = U128(1845765845647056907);
+        contract.deposit_ft(twitter_id, accounts(3), usdc(), 1_000_000, None);
+        contract.withdraw_usdc(twitter_id, accounts(3), None, Some(accounts(1)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't withdraw the share pool balance")]
+    fn test_withdraw_usdc_from_share_pool() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+
+        contract.internal_set_balance(&share_pool_key(), &usdc(), 1_000_000);
+        contract.withdraw_usdc(U128(0), accounts(0), None, None);
+    }
+
+    #[test]
+    fn test_on_withdraw_ft_success() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+
+        let twitter_id = U128(1845765845647056907);
+        contract.deposit_ft(twitter_id, accounts(3), usdc(), 1_000_000, None);
+        contract.withdraw_usdc(twitter_id, accounts(3), None, None);
+
+        set_promise_results(vec![PromiseResult::Successful(vec![])]);
+        assert!(contract.on_withdraw_ft(
+            twitter_id,
+            accounts(3),
+            usdc(),
+            U128(1_000_000),
+            accounts(3)
+        ));
+
+        let balance = contract.get_usdc_balance(twitter_id, accounts(3));
+        assert_eq!(balance.0, 0);
+        let logs = near_sdk::test_utils::get_logs();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].contains("\"event\":\"withdraw\""));
+        assert!(logs[0].contains("\"success\":true"));
+    }
+
+    #[test]
+    fn test_on_withdraw_ft_failure() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+
+        let twitter_id = U128(1845765845647056907);
+        contract.deposit_ft(twitter_id, accounts(3), usdc(), 1_000_000, None);
+        contract.withdraw_usdc(twitter_id, accounts(3), Some(U128(400_000)), None);
+
+        set_promise_results(vec![PromiseResult::Failed]);
+        assert!(!contract.on_withdraw_ft(
+            twitter_id,
+            accounts(3),
+            usdc(),
+            U128(400_000),
+            accounts(3)
+        ));
+
+        let balance = contract.get_usdc_balance(twitter_id, accounts(3));
+        assert_eq!(balance.0, 1_000_000);
+        let logs = near_sdk::test_utils::get_logs();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].contains("\"success\":false"));
+    }
+
     #[test]
     fn test_create_capital_allocation() {
         let context = get_context(accounts(1));
         testing_env!(context.build());
         let mut contract = get_contract();
 
-        let capital_id = contract.create_capital_allocation(accounts(1), U128::from(1000), Some(accounts(2)));
-        let (active, owner_id, _, positions) = contract.get_capital_allocation(capital_id);
-        assert!(active);
-        assert_eq!(owner_id, accounts(1));
-        assert_eq!(positions.len(), 0);
+        let funding = fund_allocation_with(&mut contract, &accounts(2), 1000);
+        let capital_id =
+            contract.create_capital_allocation(accounts(1), funding, Some(accounts(2)), None);
+        let view = contract.get_capital_allocation(capital_id);
+        assert!(view.status == CapitalAllocationStatus::Active);
+        assert_eq!(view.owner_id, accounts(1));
+        assert_eq!(view.positions.len(), 0);
     }
 
     #[test]
-    fn test_withdraw_capital() {
+    fn test_allocation_funded_from_deposits() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+        contract.deposit_ft(U128(1), accounts(1), usdc(), 600_000, None);
+        contract.deposit_ft(U128(3), accounts(3), usdc(), 500_000, None);
+
+        let funding = vec![
+            FundingSource {
+                twitter_id: U128(1),
+                near_account_id: accounts(1),
+                amount: U128(200_000),
+            },
+            FundingSource {
+                twitter_id: U128(3),
+                near_account_id: accounts(3),
+                amount: U128(100_000),
+            },
+        ];
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, Some(0));
+        let capital = contract.get_capital(capital_id);
+        assert_eq!(capital.entry_value.amount, 300_000);
+        assert_eq!(capital.funding.len(), 2);
+        assert_eq!(contract.get_usdc_balance(U128(1), accounts(1)).0, 400_000);
+        assert_eq!(contract.get_usdc_balance(U128(3), accounts(3)).0, 400_000);
+
+        // proceeds are split 2:1, the rounding remainder goes to the last source
+        contract.withdraw_capital(capital_id);
+        contract.set_capital_exit_value(capital_id, U128(400_001), None);
+        assert_eq!(
+            contract.get_usdc_balance(U128(1), accounts(1)).0,
+            400_000 + 266_667
+        );
+        assert_eq!(
+            contract.get_usdc_balance(U128(3), accounts(3)).0,
+            400_000 + 133_334
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough balance to withdraw")]
+    fn test_allocation_funding_above_balance() {
         let context = get_context(accounts(1));
         testing_env!(context.build());
         let mut contract = get_contract();
+        contract.deposit_ft(U128(3), accounts(3), usdc(), 500_000, None);
+
+        let funding = vec![FundingSource {
+            twitter_id: U128(3),
+            near_account_id: accounts(3),
+            amount: U128(500_001),
+        }];
+        contract.create_capital_allocation(accounts(1), funding, None, None);
+    }
 
-        contract.create_capital_allocation(accounts(1), U128::from(1000), Some(accounts(2)));
+    #[test]
+    #[should_panic(expected = "Capital Allocation must be funded")]
+    fn test_unfunded_allocation() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+
+        contract.create_capital_allocation(accounts(1), vec![], None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Capital Allocation exit value already set")]
+    fn test_set_capital_exit_value_twice() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+
+        let funding = fund_allocation(&mut contract, 1000);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, Some(0));
+        contract.withdraw_capital(capital_id);
+        contract.set_capital_exit_value(capital_id, U128(1100), None);
+        contract.set_capital_exit_value(capital_id, U128(1100), None);
+    }
+
+    #[test]
+    fn test_create_capital_allocation_with_locktime() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.block_timestamp(1_000_000_000_000).build());
+        let mut contract = get_contract();
+
+        contract.set_locktime(3_600_000);
+        let funding = fund_allocation(&mut contract, 1000);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, None);
+        let maturity = contract.get_allocation_maturity(capital_id);
+        assert_eq!(maturity.entry_timestamp, 1_000_000);
+        assert_eq!(maturity.exit_timestamp, 1_000_000 + 3_600_000);
+
+        let funding = fund_allocation(&mut contract, 1000);
+        let capital_id =
+            contract.create_capital_allocation(accounts(1), funding, None, Some(60_000));
+        let maturity = contract.get_allocation_maturity(capital_id);
+        assert_eq!(maturity.exit_timestamp, 1_000_000 + 60_000);
+        assert_eq!(maturity.remaining_ms, 60_000);
+        assert!(!maturity.is_matured);
+    }
+
+    #[test]
+    fn test_withdraw_capital() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+
+        let funding = fund_allocation_with(&mut contract, &accounts(2), 1000);
+        contract.create_capital_allocation(accounts(1), funding, Some(accounts(2)), None);
+
+        // travel past the default locktime
+        testing_env!(context
+            .block_timestamp((DEFAULT_LOCKTIME_IN_MS + 1) * 1_000_000)
+            .build());
+        assert!(contract.get_allocation_maturity(0).is_matured);
+        assert_eq!(contract.get_allocation_maturity(0).remaining_ms, 0);
         contract.withdraw_capital(0);
-        let (active, _, _, _) = contract.get_capital_allocation(0);
-        assert!(!active);
+        assert!(contract.get_capital_allocation(0).status != CapitalAllocationStatus::Active);
+    }
+    #[test]
+    #[should_panic(expected = "Capital Allocation not yet matured")]
+    fn test_withdraw_capital_before_maturity() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+
+        let funding = fund_allocation(&mut contract, 1000);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, None);
+
+        testing_env!(context
+            .block_timestamp((DEFAULT_LOCKTIME_IN_MS - 1) * 1_000_000)
+            .build());
+        contract.withdraw_capital(capital_id);
+    }
+
+    #[test]
+    fn test_exit_capital_early() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+
+        let funding = fund_allocation(&mut contract, 1000);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, None);
+        contract.add_position(capital_id, accounts(2), 1000);
+
+        testing_env!(context.block_timestamp(1_000_000_000).build());
+        contract.exit_capital_early(capital_id, EarlyExitReason::StopLoss);
+
+        let maturity = contract.get_allocation_maturity(capital_id);
+        assert!(!maturity.is_matured);
+        assert!(maturity.early_exit == Some(EarlyExitReason::StopLoss));
+        assert!(contract.get_capital(capital_id).status == CapitalAllocationStatus::Withdrawing);
+
+        // a failed early exit can be retried before maturity
+        set_promise_results(vec![PromiseResult::Failed]);
+        let legs = vec![WithdrawalLeg {
+            position_index: 0,
+            amount: U128(1000),
+        }];
+        contract.on_withdraw_capital(capital_id, legs);
+
+        testing_env!(context.block_timestamp(2_000_000_000).build());
+        contract.withdraw_capital(capital_id);
+        assert!(contract.get_capital(capital_id).status == CapitalAllocationStatus::Withdrawing);
+    }
+
+    #[test]
+    #[should_panic(expected = "Capital Allocation already matured")]
+    fn test_exit_capital_early_after_maturity() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+
+        let funding = fund_allocation(&mut contract, 1000);
+        let capital_id =
+            contract.create_capital_allocation(accounts(1), funding, None, Some(1_000));
+
+        testing_env!(context.block_timestamp(1_000 * 1_000_000).build());
+        contract.exit_capital_early(capital_id, EarlyExitReason::TakeProfit);
+    }
+
+    #[test]
+    fn test_withdraw_capital_partially() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+
+        let funding = fund_allocation(&mut contract, 1000);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, Some(0));
+        contract.add_position(capital_id, accounts(2), 600);
+        contract.add_position(capital_id, accounts(3), 400);
+        contract.withdraw_capital(capital_id);
+        assert!(contract.get_capital(capital_id).status == CapitalAllocationStatus::Withdrawing);
+
+        let legs = vec![
+            WithdrawalLeg {
+                position_index: 0,
+                amount: U128(600),
+            },
+            WithdrawalLeg {
+                position_index: 1,
+                amount: U128(400),
+            },
+        ];
+        set_promise_results(vec![
+            PromiseResult::Successful(serde_json::to_vec(&U128(600)).unwrap()),
+            PromiseResult::Failed,
+        ]);
+        let status = contract.on_withdraw_capital(capital_id, legs);
+        assert!(status == CapitalAllocationStatus::PartiallyWithdrawn);
+
+        let capital = contract.get_capital(capital_id);
+        assert_eq!(capital.positions[0].amount, 0);
+        assert_eq!(capital.positions[1].amount, 400);
+        assert_eq!(capital.withdrawn.len(), 1);
+        assert_eq!(capital.withdrawn[0].token_id, accounts(2));
+        assert_eq!(capital.withdrawn[0].amount, 600);
+
+        // retry sends only the failed leg, this time partially refunded by the receiver
+        testing_env!(get_context(accounts(1)).build());
+        contract.withdraw_capital(capital_id);
+        let legs = vec![WithdrawalLeg {
+            position_index: 1,
+            amount: U128(400),
+        }];
+        set_promise_results(vec![PromiseResult::Successful(
+            serde_json::to_vec(&U128(300)).unwrap(),
+        )]);
+        let status = contract.on_withdraw_capital(capital_id, legs);
+        assert!(status == CapitalAllocationStatus::PartiallyWithdrawn);
+        assert_eq!(contract.get_capital(capital_id).positions[1].amount, 100);
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.withdraw_capital(capital_id);
+        let legs = vec![WithdrawalLeg {
+            position_index: 1,
+            amount: U128(100),
+        }];
+        set_promise_results(vec![PromiseResult::Successful(
+            serde_json::to_vec(&U128(100)).unwrap(),
+        )]);
+        let status = contract.on_withdraw_capital(capital_id, legs);
+        assert!(status == CapitalAllocationStatus::Withdrawn);
+
+        let capital = contract.get_capital(capital_id);
+        assert_eq!(capital.withdrawn[1].token_id, accounts(3));
+        assert_eq!(capital.withdrawn[1].amount, 400);
+    }
+
+    #[test]
+    fn test_withdraw_capital_failed() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+
+        let funding = fund_allocation(&mut contract, 1000);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, Some(0));
+        contract.add_position(capital_id, accounts(2), 1000);
+        contract.withdraw_capital(capital_id);
+
+        set_promise_results(vec![PromiseResult::Failed]);
+        let legs = vec![WithdrawalLeg {
+            position_index: 0,
+            amount: U128(1000),
+        }];
+        let status = contract.on_withdraw_capital(capital_id, legs);
+        assert!(status == CapitalAllocationStatus::Failed);
+        assert_eq!(contract.get_capital(capital_id).positions[0].amount, 1000);
+        assert!(contract.get_capital(capital_id).withdrawn.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Capital Allocation already withdrawn")]
+    fn test_withdraw_capital_while_withdrawing() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+
+        let funding = fund_allocation(&mut contract, 1000);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, Some(0));
+        contract.add_position(capital_id, accounts(2), 1000);
+        contract.withdraw_capital(capital_id);
+        contract.withdraw_capital(capital_id);
+    }
+
+    #[test]
+    fn test_recover_stuck_withdrawal() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+
+        let funding = fund_allocation(&mut contract, 1000);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, Some(0));
+        contract.add_position(capital_id, accounts(2), 1000);
+        contract.withdraw_capital(capital_id);
+
+        // the callback never ran, the owner releases the allocation for a retry
+        contract.set_allocation_status_to_failed(capital_id, vec![]);
+        assert!(contract.get_capital(capital_id).status == CapitalAllocationStatus::Failed);
+        contract.withdraw_capital(capital_id);
+        assert!(contract.get_capital(capital_id).status == CapitalAllocationStatus::Withdrawing);
     }
+
+    #[test]
+    fn test_recover_stuck_withdrawal_with_landed_leg() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+
+        let funding = fund_allocation(&mut contract, 1000);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, Some(0));
+        contract.add_position(capital_id, accounts(2), 600);
+        contract.add_position(capital_id, accounts(4), 400);
+        contract.withdraw_capital(capital_id);
+
+        // the first leg was found in intents, only the second one is sent again
+        contract.set_allocation_status_to_failed(
+            capital_id,
+            vec![WithdrawalLeg {
+                position_index: 0,
+                amount: U128(600),
+            }],
+        );
+        let capital = contract.get_capital(capital_id);
+        assert!(capital.status == CapitalAllocationStatus::PartiallyWithdrawn);
+        assert_eq!(capital.withdrawn[0].amount, 600);
+
+        contract.withdraw_capital(capital_id);
+        let legs = contract.get_capital(capital_id).positions;
+        assert_eq!(legs[0].amount, 0);
+        assert_eq!(legs[1].amount, 400);
+    }
+
+    #[test]
+    #[should_panic(expected = "Landed amount exceeds the position")]
+    fn test_recover_stuck_withdrawal_above_position() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+
+        let funding = fund_allocation(&mut contract, 1000);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, Some(0));
+        contract.add_position(capital_id, accounts(2), 1000);
+        contract.withdraw_capital(capital_id);
+        contract.set_allocation_status_to_failed(
+            capital_id,
+            vec![WithdrawalLeg {
+                position_index: 0,
+                amount: U128(1001),
+            }],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Capital Allocation is not withdrawing")]
+    fn test_recover_active_allocation() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+
+        let funding = fund_allocation(&mut contract, 1000);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, Some(0));
+        contract.set_allocation_status_to_failed(capital_id, vec![]);
+    }
+
     #[test]
     fn test_full_leaderboard() {
         let context = get_context(accounts(1));
@@ -576,59 +1833,65 @@ mod tests {
         let mut contract = get_contract();
 
         // 100% profit deal
-        let capital_id = contract.create_capital_allocation(accounts(1), U128::from(1000), None);
+        let funding = fund_allocation(&mut contract, 1000);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, Some(0));
         contract.withdraw_capital(capital_id);
 
         contract.set_capital_exit_value(capital_id, U128::from(2000), None);
-        let (active, _, _, _) = contract.get_capital_allocation(0);
-        assert!(!active);
+        assert!(contract.get_capital_allocation(0).status != CapitalAllocationStatus::Active);
 
         // add leaderboard checks
-        assert_eq!(contract.leaderboard.profit.len(), 1);
-        assert_eq!(contract.leaderboard.loss.len(), 0);
-
-        assert_eq!(contract.leaderboard.profit[0].value, 100);
-        assert_eq!(contract.leaderboard.profit[0].capital_id, capital_id);
+        assert_eq!(contract.get_leaderboard().len(), 1);
+        assert_eq!(contract.get_leaderboard()[0].pnl_bps.0, 10_000);
+        assert_eq!(contract.get_leaderboard()[0].capital_id, capital_id);
+        assert_eq!(contract.get_capital(capital_id).pnl_bps.unwrap().0, 10_000);
+        let json = serde_json::to_value(contract.get_leaderboard()).unwrap();
+        assert_eq!(json[0]["pnl_bps"], "10000");
 
         // 33% profit deal
-        let capital_id = contract.create_capital_allocation(accounts(1), U128::from(100), None);
+        let funding = fund_allocation(&mut contract, 100);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, Some(0));
         contract.withdraw_capital(capital_id);
 
         contract.set_capital_exit_value(capital_id, U128::from(133), None);
-        let (active, _, _, _) = contract.get_capital_allocation(0);
-        assert!(!active);
-
-        // add leaderboard checks
-        assert_eq!(contract.leaderboard.profit.len(), 2);
-        assert_eq!(contract.leaderboard.loss.len(), 0);
-
-        assert_eq!(contract.leaderboard.profit[0].value, 100);
-        assert_eq!(contract.leaderboard.profit[1].value, 33);
-        assert_eq!(contract.leaderboard.profit[1].capital_id, capital_id);
 
+        assert_eq!(contract.get_leaderboard().len(), 2);
+        assert_eq!(contract.get_leaderboard()[0].pnl_bps.0, 10_000);
+        assert_eq!(contract.get_leaderboard()[1].pnl_bps.0, 3_300);
+        assert_eq!(contract.get_leaderboard()[1].capital_id, capital_id);
 
         // - 50% profit deal
-        let capital_id = contract.create_capital_allocation(accounts(1), U128::from(1000), None);
+        let funding = fund_allocation(&mut contract, 1000);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, Some(0));
         contract.withdraw_capital(capital_id);
 
         contract.set_capital_exit_value(capital_id, U128::from(500), None);
-        let (active, _, _, _) = contract.get_capital_allocation(1);
-        assert!(!active);
 
-        assert_eq!(contract.leaderboard.profit.len(), 2);
-        assert_eq!(contract.leaderboard.loss[0].value, 50);
-        assert_eq!(contract.leaderboard.loss[0].capital_id, capital_id);
+        assert_eq!(contract.get_leaderboard().len(), 3);
+        assert_eq!(contract.get_leaderboard()[2].pnl_bps.0, -5_000);
+        assert_eq!(contract.get_leaderboard()[2].capital_id, capital_id);
+
+        // -0.9% deal ranks below break-even ones
+        let funding = fund_allocation(&mut contract, 1000);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, Some(0));
+        contract.withdraw_capital(capital_id);
+
+        contract.set_capital_exit_value(capital_id, U128::from(991), None);
+
+        assert_eq!(contract.get_leaderboard().len(), 4);
+        assert_eq!(contract.get_leaderboard()[2].pnl_bps.0, -90);
+        assert_eq!(contract.get_leaderboard()[2].capital_id, capital_id);
 
         // 0% profit deal
-        let capital_id = contract.create_capital_allocation(accounts(1), U128::from(500), None);
+        let funding = fund_allocation(&mut contract, 500);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, Some(0));
         contract.withdraw_capital(capital_id);
 
         contract.set_capital_exit_value(capital_id, U128::from(500), None);
-        let (active, _, _, _) = contract.get_capital_allocation(1);
-        assert!(!active);
 
-        assert_eq!(contract.leaderboard.profit.len(), 3);
-        assert_eq!(contract.leaderboard.profit[2].value, 0);
-        assert_eq!(contract.leaderboard.profit[2].capital_id, capital_id);
+        assert_eq!(contract.get_leaderboard().len(), 5);
+        assert_eq!(contract.get_leaderboard()[2].pnl_bps.0, 0);
+        assert_eq!(contract.get_leaderboard()[2].capital_id, capital_id);
+        assert_eq!(contract.get_leaderboard().len(), 5);
     }
-}
\ No newline at end of file
+}
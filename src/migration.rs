@@ -0,0 +1,525 @@
+use crate::*;
+use near_sdk::json_types::Base64VecU8;
+
+// bump and freeze the previous layout in `VersionedContract` when releasing a new `Contract` layout
+const CURRENT_STATE_VERSION: u8 = 1;
+const STATE_KEY: &[u8] = b"STATE";
+const STATE_VERSION_KEY: &[u8] = b"STATE_VERSION";
+const GAS_MIGRATE: Gas = Gas::from_tgas(50);
+
+// the token accounted in v0 `usdc_deposits`
+const USDC_CONTRACT_ID_V0: &str =
+    "17208628f84f5d6ad33f0da3bbbeb27ffcb398eac501a31bd6ad2011e36133a1";
+// the receiver of v0 capital withdrawals
+const INTENTS_CONTRACT_ID_V0: &str = "intents.near";
+
+#[derive(BorshSerialize, BorshStorageKey)]
+#[borsh(crate = "near_sdk::borsh")]
+enum StorageKeyV0 {
+    NearDeposits,
+    UsdcDeposits,
+    CapitalAllocations,
+}
+
+#[near(serializers = [borsh])]
+pub struct AssetPositionV0 {
+    pub token_id: AccountId,
+    pub amount: Balance,
+}
+
+#[near(serializers = [borsh])]
+pub enum CapitalAllocationStatusV0 {
+    Active,
+    Withdrawn,
+}
+
+#[near(serializers = [borsh])]
+pub struct CapitalAllocationV0 {
+    pub owner_id: AccountId,
+    pub status: CapitalAllocationStatusV0,
+    pub positions: Vec<AssetPositionV0>,
+    pub entry_timestamp: Timestamp,
+    pub exit_timestamp: Timestamp,
+    pub entry_value: AssetPositionV0,
+    pub exit_value: Option<AssetPositionV0>,
+}
+
+#[near(serializers = [borsh])]
+pub struct LeaderboardItemV0 {
+    pub near_account_id: AccountId,
+    pub value: u128,
+    pub capital_id: u64,
+}
+
+#[near(serializers = [borsh])]
+pub struct LeaderboardV0 {
+    pub profit: Vec<LeaderboardItemV0>,
+    pub loss: Vec<LeaderboardItemV0>,
+}
+
+#[near(serializers = [borsh])]
+pub struct ContractV0 {
+    pub agent: String,
+    pub agent_account_id: AccountId,
+    pub locktime: u64,
+    pub near_deposits: LookupMap<TwitterNearAccount, Balance>,
+    pub usdc_deposits: LookupMap<TwitterNearAccount, Balance>,
+    pub leaderboard: LeaderboardV0,
+    pub capital: LookupMap<u64, CapitalAllocationV0>,
+    pub next_capital_id: u64,
+}
+
+// picked by `STATE_VERSION_KEY`, the variants are deserialized on their own
+pub enum VersionedContract {
+    V0(ContractV0),
+    Current(Box<Contract>),
+}
+
+#[near(serializers = [borsh])]
+pub enum VersionedCapitalAllocation {
+    V0(CapitalAllocationV0),
+    Current(CapitalAllocation),
+}
+
+impl From<AssetPositionV0> for AssetPosition {
+    fn from(position: AssetPositionV0) -> Self {
+        Self {
+            token_id: position.token_id,
+            amount: position.amount,
+        }
+    }
+}
+
+impl From<CapitalAllocationV0> for CapitalAllocation {
+    fn from(capital: CapitalAllocationV0) -> Self {
+        let pnl_bps = capital
+            .exit_value
+            .as_ref()
+            .and_then(|exit_value| pnl_bps(capital.entry_value.amount, exit_value.amount))
+            .map(I128::from);
+        Self {
+            owner_id: capital.owner_id,
+            status: match capital.status {
+                CapitalAllocationStatusV0::Active => CapitalAllocationStatus::Active,
+                CapitalAllocationStatusV0::Withdrawn => CapitalAllocationStatus::Withdrawn,
+            },
+            positions: capital.positions.into_iter().map(Into::into).collect(),
+            // v0 didn't track settlement, the positions were sent out as a whole
+            withdrawn: vec![],
+            entry_timestamp: capital.entry_timestamp,
+            exit_timestamp: capital.exit_timestamp,
+            entry_value: capital.entry_value.into(),
+            exit_value: capital.exit_value.map(Into::into),
+            early_exit: None,
+            // v0 allocations weren't funded from deposits
+            funding: vec![],
+            pnl_bps,
+            intents_deposit: None,
+            pending_reductions: 0,
+        }
+    }
+}
+
+impl From<VersionedCapitalAllocation> for CapitalAllocation {
+    fn from(capital: VersionedCapitalAllocation) -> Self {
+        match capital {
+            VersionedCapitalAllocation::V0(capital) => capital.into(),
+            VersionedCapitalAllocation::Current(capital) => capital,
+        }
+    }
+}
+
+impl LeaderboardV0 {
+    // v0 kept whole percents in separate profit and loss lists
+    fn into_current(self) -> Leaderboard {
+        let into_item = |item: LeaderboardItemV0, sign: i128| LeaderboardItem {
+            near_account_id: item.near_account_id,
+            pnl_bps: I128(sign * (item.value as i128) * 100),
+            capital_id: item.capital_id,
+        };
+        let mut current = Leaderboard::new(StorageKey::Leaderboard);
+        for item in self.profit {
+            current.add_item(into_item(item, 1), DEFAULT_LEADERBOARD_LENGTH);
+        }
+        for item in self.loss {
+            current.add_item(into_item(item, -1), DEFAULT_LEADERBOARD_LENGTH);
+        }
+        current
+    }
+}
+
+impl From<VersionedContract> for Contract {
+    fn from(contract: VersionedContract) -> Self {
+        match contract {
+            VersionedContract::V0(contract) => {
+                // v0 deposits and allocations stay under their old prefixes and are read lazily
+                let mut current = Self {
+                    agent: contract.agent,
+                    agent_account_id: contract.agent_account_id,
+
+                    locktime: contract.locktime,
+                    // v0 hard-coded the mainnet ids, whichever network the new code was built for
+                    config: Config {
+                        usdc_contract_id: AccountId::from_str(USDC_CONTRACT_ID_V0).unwrap(),
+                        intents_contract_id: AccountId::from_str(INTENTS_CONTRACT_ID_V0).unwrap(),
+                        ..Config::default()
+                    },
+
+                    tokens: UnorderedMap::new(StorageKey::Tokens),
+                    removed_tokens: UnorderedSet::new(StorageKey::RemovedTokens),
+                    balances: LookupMap::new(StorageKey::Balances),
+
+                    leaderboard: contract.leaderboard.into_current(),
+                    leaderboard_length: DEFAULT_LEADERBOARD_LENGTH,
+
+                    capital: LookupMap::new(StorageKey::CapitalAllocations),
+                    next_capital_id: contract.next_capital_id,
+                    allocations_by_owner: LookupMap::new(StorageKey::AllocationsByOwner),
+                    active_allocations: UnorderedSet::new(StorageKey::ActiveAllocations),
+                    // indexed in batches by `reindex_allocations`, a single pass may not fit in gas
+                    legacy_allocations: contract.next_capital_id,
+                    reindexed_allocations: 0,
+                    position_history: LookupMap::new(StorageKey::PositionHistory),
+
+                    storage_accounts: LookupMap::new(StorageKey::StorageAccounts),
+                    account_storage_usage: 0,
+                    ledger_entry_storage_usage: 0,
+
+                    roles: UnorderedMap::new(StorageKey::Roles),
+                    pause_status: PauseStatus::default(),
+
+                    withdrawal_recipients: LookupMap::new(StorageKey::WithdrawalRecipients),
+                    withdrawal_requests: UnorderedMap::new(StorageKey::WithdrawalRequests),
+                    withdrawal_requests_by_account: LookupMap::new(
+                        StorageKey::WithdrawalRequestsByAccount,
+                    ),
+                    next_withdrawal_request_id: 0,
+                    withdrawal_timeout: DEFAULT_WITHDRAWAL_TIMEOUT_IN_MS,
+
+                    emergency_mode: false,
+
+                    shares: near_contract_standards::fungible_token::FungibleToken::new(
+                        StorageKey::Shares,
+                    ),
+                    shares_allocated: 0,
+
+                    fees: FeeConfig::default(),
+                    treasury_id: env::current_account_id(),
+                    treasury_balances: UnorderedMap::new(StorageKey::TreasuryBalances),
+                    high_water_marks: LookupMap::new(StorageKey::HighWaterMarks),
+
+                    seasons: Vector::new(StorageKey::Seasons),
+                    open_seasons: vec![],
+
+                    // v0 didn't keep history, aggregates start from the first settlement after the upgrade
+                    account_stats: LookupMap::new(StorageKey::AccountStats),
+                    account_leaderboard: Leaderboard::new(StorageKey::AccountLeaderboard),
+                };
+                current.measure_account_storage_usage();
+                current.measure_ledger_entry_storage_usage();
+                current.whitelist_default_tokens();
+                // v0 owner methods were `#[private]`, the agent could call everything else
+                current.internal_grant_role(&env::current_account_id(), Role::Owner);
+                current.internal_grant_role(&current.agent_account_id.clone(), Role::Agent);
+                current
+            }
+            VersionedContract::Current(contract) => *contract,
+        }
+    }
+}
+
+pub(crate) fn write_state_version() {
+    env::storage_write(STATE_VERSION_KEY, &[CURRENT_STATE_VERSION]);
+}
+
+fn read_versioned_state() -> VersionedContract {
+    let state = env::storage_read(STATE_KEY).expect("Contract is not initialized");
+    // v0 was deployed before the version key existed
+    let version = env::storage_read(STATE_VERSION_KEY).map_or(0, |version| version[0]);
+    match version {
+        0 => VersionedContract::V0(
+            ContractV0::try_from_slice(&state).expect("Failed to deserialize v0 state"),
+        ),
+        CURRENT_STATE_VERSION => VersionedContract::Current(Box::new(
+            Contract::try_from_slice(&state).expect("Failed to deserialize state"),
+        )),
+        _ => env::panic_str("Unknown state version"),
+    }
+}
+
+fn legacy_deposits(token_id: &AccountId) -> Option<LookupMap<TwitterNearAccount, Balance>> {
+    match token_id.as_str() {
+        NATIVE_NEAR_TOKEN_ID => Some(LookupMap::new(StorageKeyV0::NearDeposits)),
+        USDC_CONTRACT_ID_V0 => Some(LookupMap::new(StorageKeyV0::UsdcDeposits)),
+        _ => None,
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    // deploys new code and migrates the state in the same receipt chain
+    pub fn upgrade(&self, code: Base64VecU8) -> Promise {
+        self.require_role(Role::Owner);
+        Promise::new(env::current_account_id())
+            .deploy_contract(code.into())
+            .function_call(
+                "migrate".to_string(),
+                vec![],
+                NearToken::from_yoctonear(0),
+                GAS_MIGRATE,
+            )
+    }
+
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let contract: Contract = read_versioned_state().into();
+        write_state_version();
+        contract
+    }
+
+    // adds v0 allocations to the owner and active indexes, returns the next `from_index`
+    pub fn reindex_allocations(&mut self, from_index: u64, limit: u64) -> u64 {
+        self.require_role(Role::Owner);
+        assert_eq!(
+            from_index, self.reindexed_allocations,
+            "Allocations are reindexed in order"
+        );
+        let to_index = from_index
+            .saturating_add(limit)
+            .min(self.legacy_allocations)
+            .max(from_index);
+        for capital_id in from_index..to_index {
+            // the record may have been upgraded on an update since the migration
+            if let Some(capital) = self.internal_find_capital(capital_id) {
+                self.internal_index_owner_allocation(&capital.owner_id, capital_id);
+                self.internal_index_allocation_status(capital_id, &capital.status);
+            }
+        }
+        self.reindexed_allocations = to_index;
+        to_index
+    }
+
+    pub fn get_state_version(&self) -> u8 {
+        env::storage_read(STATE_VERSION_KEY).map_or(0, |version| version[0])
+    }
+}
+
+impl Contract {
+    pub(crate) fn internal_get_capital(&self, capital_id: u64) -> CapitalAllocation {
+        self.internal_find_capital(capital_id)
+            .expect("Capital Allocation not found")
+    }
+
+    pub(crate) fn internal_find_capital(&self, capital_id: u64) -> Option<CapitalAllocation> {
+        self.capital
+            .get(&capital_id)
+            .or_else(|| {
+                LookupMap::<u64, CapitalAllocationV0>::new(StorageKeyV0::CapitalAllocations)
+                    .get(&capital_id)
+                    .map(VersionedCapitalAllocation::V0)
+            })
+            .map(Into::into)
+    }
+
+    // always writes the current version, so v0 records are upgraded on their first update
+    pub(crate) fn internal_set_capital(&mut self, capital_id: u64, capital: &CapitalAllocation) {
+        self.capital.insert(
+            &capital_id,
+            &VersionedCapitalAllocation::Current(capital.clone()),
+        );
+        self.internal_index_allocation_status(capital_id, &capital.status);
+    }
+
+    pub(crate) fn internal_get_legacy_balance(
+        &self,
+        key: &TwitterNearAccount,
+        token_id: &AccountId,
+    ) -> Option<Balance> {
+        legacy_deposits(token_id).and_then(|deposits| deposits.get(key))
+    }
+
+    pub(crate) fn internal_remove_legacy_balance(
+        &mut self,
+        key: &TwitterNearAccount,
+        token_id: &AccountId,
+    ) -> bool {
+        legacy_deposits(token_id).is_some_and(|mut deposits| deposits.remove(key).is_some())
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::borsh;
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::get_context;
+
+    // writes the state exactly as the v0 contract laid it out
+    fn write_v0_state() {
+        let profit_item = (accounts(2), 100u128, 0u64);
+        let state = borsh::to_vec(&(
+            "agent.near".to_string(),
+            accounts(1),
+            3_600_000u64,
+            vec![0u8],
+            vec![1u8],
+            (vec![profit_item], vec![(accounts(3), 20u128, 1u64)]),
+            vec![2u8],
+            2u64,
+        ))
+        .unwrap();
+        env::storage_write(STATE_KEY, &state);
+
+        let depositor = borsh::to_vec(&(1845765845647056907u128, accounts(3))).unwrap();
+        env::storage_write(
+            &[&[0u8][..], &depositor].concat(),
+            &borsh::to_vec(&5_000u128).unwrap(),
+        );
+        env::storage_write(
+            &[&[1u8][..], &depositor].concat(),
+            &borsh::to_vec(&700_000u128).unwrap(),
+        );
+
+        let usdc = AccountId::from_str(USDC_CONTRACT_ID_V0).unwrap();
+        let capital = borsh::to_vec(&(
+            accounts(2),
+            1u8, // Withdrawn
+            vec![(accounts(4), 1_000u128)],
+            1_000u64,
+            2_000u64,
+            (usdc.clone(), 1_000u128),
+            Some((usdc, 2_000u128)),
+        ))
+        .unwrap();
+        env::storage_write(&[&[2u8][..], &0u64.to_le_bytes()].concat(), &capital);
+
+        let usdc = AccountId::from_str(USDC_CONTRACT_ID_V0).unwrap();
+        let unsettled = borsh::to_vec(&(
+            accounts(3),
+            1u8, // Withdrawn
+            vec![(accounts(4), 500u128)],
+            1_000u64,
+            2_000u64,
+            (usdc, 1_000u128),
+            None::<(AccountId, u128)>,
+        ))
+        .unwrap();
+        env::storage_write(&[&[2u8][..], &1u64.to_le_bytes()].concat(), &unsettled);
+    }
+
+    #[test]
+    fn test_migrate_v0() {
+        testing_env!(get_context(accounts(0)).build());
+        write_v0_state();
+
+        let mut contract = Contract::migrate();
+        assert_eq!(contract.get_state_version(), CURRENT_STATE_VERSION);
+        assert_eq!(contract.agent, "agent.near");
+        assert_eq!(contract.agent_account_id, accounts(1));
+        assert_eq!(contract.get_locktime(), 3_600_000);
+        assert_eq!(contract.next_capital_id, 2);
+        assert_eq!(contract.get_leaderboard().len(), 2);
+        assert_eq!(contract.get_leaderboard()[0].near_account_id, accounts(2));
+        assert_eq!(contract.get_leaderboard()[0].pnl_bps.0, 10_000);
+        assert_eq!(contract.get_leaderboard()[1].pnl_bps.0, -2_000);
+        assert_eq!(contract.get_whitelisted_tokens().len(), 2);
+        assert_eq!(
+            contract.get_config().usdc_contract_id.as_str(),
+            USDC_CONTRACT_ID_V0
+        );
+        assert_eq!(
+            contract.get_config().intents_contract_id.as_str(),
+            INTENTS_CONTRACT_ID_V0
+        );
+        assert_eq!(contract.get_roles(accounts(0)), vec![Role::Owner]);
+        assert_eq!(contract.get_roles(accounts(1)), vec![Role::Agent]);
+
+        let twitter_id = U128(1845765845647056907);
+        assert_eq!(contract.get_near_balance(twitter_id, accounts(3)).0, 5_000);
+        assert_eq!(
+            contract.get_usdc_balance(twitter_id, accounts(3)).0,
+            700_000
+        );
+
+        let capital = contract.get_capital(0);
+        assert!(capital.status == CapitalAllocationStatus::Withdrawn);
+        assert_eq!(capital.owner_id, accounts(2));
+        assert_eq!(capital.positions[0].token_id, accounts(4));
+        assert_eq!(capital.exit_value.unwrap().amount, 2_000);
+        assert!(capital.early_exit.is_none());
+        assert_eq!(capital.pnl_bps.unwrap().0, 10_000);
+        // v0 allocations are indexed in batches after the upgrade
+        assert!(contract
+            .get_allocations_by_owner(accounts(2), None, None)
+            .is_empty());
+        assert_eq!(contract.reindex_allocations(0, 1), 1);
+        assert_eq!(contract.reindex_allocations(1, 10), 2);
+        assert_eq!(contract.reindex_allocations(2, 10), 2);
+        let entries = contract.get_allocations_by_owner(accounts(2), None, None);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].capital_id, 0);
+        assert!(contract.get_active_allocations(None, None).is_empty());
+
+        // the first update moves the record and the balance to the current layout
+        contract.grant_role(accounts(1), Role::Operator);
+        testing_env!(get_context(accounts(1)).build());
+        contract.withdraw_usdc(twitter_id, accounts(3), Some(U128(200_000)), None);
+        assert_eq!(
+            contract.get_usdc_balance(twitter_id, accounts(3)).0,
+            500_000
+        );
+        let key = TwitterNearAccount {
+            twitter_id: twitter_id.0,
+            near_account_id: accounts(3),
+        };
+        assert!(contract
+            .internal_get_legacy_balance(&key, &AccountId::from_str(USDC_CONTRACT_ID_V0).unwrap())
+            .is_none());
+    }
+
+    #[test]
+    fn test_settle_v0_allocation() {
+        testing_env!(get_context(accounts(0)).build());
+        write_v0_state();
+        let mut contract = Contract::migrate();
+        contract.grant_role(accounts(1), Role::Operator);
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.set_capital_exit_value(1, U128(1_500), None);
+
+        // without funding sources the proceeds go to the owner's own ledger entry
+        let usdc = AccountId::from_str(USDC_CONTRACT_ID_V0).unwrap();
+        assert_eq!(contract.get_balance(U128(0), accounts(3), usdc).0, 1_500);
+        assert_eq!(contract.get_capital(1).pnl_bps.unwrap().0, 5_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Allocations are reindexed in order")]
+    fn test_reindex_allocations_twice() {
+        testing_env!(get_context(accounts(0)).build());
+        write_v0_state();
+        let mut contract = Contract::migrate();
+        contract.reindex_allocations(0, 10);
+        contract.reindex_allocations(0, 10);
+    }
+
+    #[test]
+    fn test_migrate_current() {
+        testing_env!(get_context(accounts(0)).build());
+        let contract = Contract::new(
+            "agent.near".to_string(),
+            accounts(1),
+            accounts(0),
+            Some(60_000),
+            None,
+        );
+        env::state_write(&contract);
+
+        let contract = Contract::migrate();
+        assert_eq!(contract.get_state_version(), CURRENT_STATE_VERSION);
+        assert_eq!(contract.get_locktime(), 60_000);
+    }
+}
@@ -0,0 +1,218 @@
+use crate::*;
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PausableOperation {
+    // `deposit_near` and `Deposit` through `ft_on_transfer`
+    Deposits,
+    // payouts of user balances
+    Withdrawals,
+    Allocations,
+    // transfers of allocated capital to intents
+    CapitalWithdrawals,
+    ExitValues,
+}
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Default)]
+pub struct PauseStatus {
+    pub deposits: bool,
+    pub withdrawals: bool,
+    pub allocations: bool,
+    pub capital_withdrawals: bool,
+    pub exit_values: bool,
+}
+
+impl PauseStatus {
+    fn flag_mut(&mut self, operation: PausableOperation) -> &mut bool {
+        match operation {
+            PausableOperation::Deposits => &mut self.deposits,
+            PausableOperation::Withdrawals => &mut self.withdrawals,
+            PausableOperation::Allocations => &mut self.allocations,
+            PausableOperation::CapitalWithdrawals => &mut self.capital_withdrawals,
+            PausableOperation::ExitValues => &mut self.exit_values,
+        }
+    }
+
+    pub fn is_paused(&self, operation: PausableOperation) -> bool {
+        match operation {
+            PausableOperation::Deposits => self.deposits,
+            PausableOperation::Withdrawals => self.withdrawals,
+            PausableOperation::Allocations => self.allocations,
+            PausableOperation::CapitalWithdrawals => self.capital_withdrawals,
+            PausableOperation::ExitValues => self.exit_values,
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn set_paused(&mut self, operation: PausableOperation, paused: bool) {
+        self.require_role(Role::Pauser);
+        let flag = self.pause_status.flag_mut(operation);
+        if *flag != paused {
+            *flag = paused;
+            VaultEvent::PauseToggled(PauseToggledEvent {
+                operation,
+                paused,
+                account_id: env::predecessor_account_id(),
+            })
+            .emit();
+        }
+    }
+
+    pub fn get_pause_status(&self) -> PauseStatus {
+        self.pause_status.clone()
+    }
+}
+
+impl Contract {
+    pub(crate) fn assert_not_paused(&self, operation: PausableOperation) {
+        assert!(
+            !self.pause_status.is_paused(operation),
+            "{:?} are paused",
+            operation
+        );
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_logs};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::{fund_allocation, get_context};
+
+    // owned by accounts(0), accounts(1) is the agent, accounts(2) the pauser
+    fn get_contract() -> Contract {
+        testing_env!(get_context(accounts(0)).build());
+        let mut contract = Contract::new(
+            "agent.near".to_string(),
+            accounts(1),
+            accounts(0),
+            None,
+            None,
+        );
+        contract.internal_storage_deposit(&accounts(1), NearToken::from_near(1).as_yoctonear());
+        contract.internal_storage_deposit(&accounts(3), NearToken::from_near(1).as_yoctonear());
+        contract.grant_role(accounts(2), Role::Pauser);
+        contract.grant_role(accounts(1), Role::Operator);
+        contract
+    }
+
+    fn pause(contract: &mut Contract, operation: PausableOperation) {
+        testing_env!(get_context(accounts(2)).build());
+        contract.set_paused(operation, true);
+    }
+
+    #[test]
+    fn test_set_paused() {
+        let mut contract = get_contract();
+        pause(&mut contract, PausableOperation::Deposits);
+
+        let logs = get_logs();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].contains("\"event\":\"pause_toggled\""));
+        assert!(logs[0].contains("\"operation\":\"Deposits\""));
+        assert!(logs[0].contains("\"paused\":true"));
+
+        let status = contract.get_pause_status();
+        assert!(status.deposits);
+        assert!(!status.withdrawals);
+
+        // toggling to the current value is a no-op
+        testing_env!(get_context(accounts(2)).build());
+        contract.set_paused(PausableOperation::Deposits, true);
+        assert!(get_logs().is_empty());
+
+        contract.set_paused(PausableOperation::Deposits, false);
+        assert!(!contract.get_pause_status().deposits);
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires Pauser role")]
+    fn test_set_paused_not_pauser() {
+        let mut contract = get_contract();
+        testing_env!(get_context(accounts(1)).build());
+        contract.set_paused(PausableOperation::Allocations, true);
+    }
+
+    #[test]
+    #[should_panic(expected = "Deposits are paused")]
+    fn test_deposit_near_paused() {
+        let mut contract = get_contract();
+        pause(&mut contract, PausableOperation::Deposits);
+
+        testing_env!(get_context(accounts(3))
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.deposit_near(U128(1));
+    }
+
+    #[test]
+    fn test_ft_deposit_paused() {
+        let mut contract = get_contract();
+        pause(&mut contract, PausableOperation::Deposits);
+
+        testing_env!(get_context(AccountId::from_str(DEFAULT_USDC_CONTRACT_ID).unwrap()).build());
+        let msg = r#"{"Deposit":{"twitter_id":"42","input_tweet_id":null}}"#.to_string();
+        let unused = match contract.ft_on_transfer(accounts(3), U128(200_000), msg) {
+            PromiseOrValue::Value(unused) => unused.0,
+            PromiseOrValue::Promise(_) => panic!("Expected a value"),
+        };
+        assert_eq!(unused, 200_000);
+        assert!(get_logs()
+            .last()
+            .unwrap()
+            .contains("\"reason\":\"deposits_paused\""));
+    }
+
+    #[test]
+    #[should_panic(expected = "Withdrawals are paused")]
+    fn test_withdraw_paused() {
+        let mut contract = get_contract();
+        pause(&mut contract, PausableOperation::Withdrawals);
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.withdraw_usdc(U128(1), accounts(3), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Allocations are paused")]
+    fn test_create_allocation_paused() {
+        let mut contract = get_contract();
+        pause(&mut contract, PausableOperation::Allocations);
+
+        testing_env!(get_context(accounts(1)).build());
+        let funding = fund_allocation(&mut contract, 1000);
+        contract.create_capital_allocation(accounts(1), funding, None, Some(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "CapitalWithdrawals are paused")]
+    fn test_withdraw_capital_paused() {
+        let mut contract = get_contract();
+        testing_env!(get_context(accounts(1)).build());
+        let funding = fund_allocation(&mut contract, 1000);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, Some(0));
+        pause(&mut contract, PausableOperation::CapitalWithdrawals);
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.withdraw_capital(capital_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "ExitValues are paused")]
+    fn test_set_exit_value_paused() {
+        let mut contract = get_contract();
+        testing_env!(get_context(accounts(1)).build());
+        let funding = fund_allocation(&mut contract, 1000);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, Some(0));
+        contract.withdraw_capital(capital_id);
+        pause(&mut contract, PausableOperation::ExitValues);
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.set_capital_exit_value(capital_id, U128(1000), None);
+    }
+}
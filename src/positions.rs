@@ -0,0 +1,373 @@
+use crate::*;
+use near_sdk::IntoStorageKey;
+
+const GAS_RESOLVE_REDUCE_POSITION: Gas = Gas::from_tgas(10);
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone, PartialEq, Debug)]
+pub enum PositionChangeKind {
+    Added,
+    // sent to intents while the allocation is active
+    Reduced,
+    // sent to intents when the allocation was withdrawn
+    Withdrawn,
+}
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct PositionChange {
+    pub kind: PositionChangeKind,
+    pub token_id: AccountId,
+    pub amount: U128,
+    pub timestamp: Timestamp,
+}
+
+fn position_history_prefix(capital_id: u64) -> Vec<u8> {
+    let mut prefix = StorageKey::PositionHistory.into_storage_key();
+    prefix.extend(capital_id.to_le_bytes());
+    prefix
+}
+
+#[near_bindgen]
+impl Contract {
+    // sends part of an active position to intents, the refunded part is put back on the position
+    pub fn reduce_position(
+        &mut self,
+        capital_id: u64,
+        token_id: AccountId,
+        amount: U128,
+    ) -> Promise {
+        self.require_role(Role::Agent);
+        self.assert_not_paused(PausableOperation::CapitalWithdrawals);
+        self.assert_not_emergency();
+        assert!(amount.0 > 0, "Amount must be positive");
+        assert!(
+            env::prepaid_gas().as_gas()
+                >= self.config.gas_ft_transfer_call.as_gas() + GAS_RESOLVE_REDUCE_POSITION.as_gas(),
+            "Not enough gas to reduce position"
+        );
+
+        let mut capital = self.internal_get_capital(capital_id);
+        assert!(
+            capital.status == CapitalAllocationStatus::Active,
+            "Capital Allocation is not active"
+        );
+        // the position history of the landed part is charged to the owner in the callback
+        self.assert_storage_covered(&capital.storage_payer_id());
+        let position = capital
+            .positions
+            .iter_mut()
+            .find(|position| position.token_id == token_id)
+            .expect("Position not found");
+        assert!(position.amount >= amount.0, "Not enough amount in position");
+        position.amount -= amount.0;
+        capital.pending_reductions += 1;
+        self.internal_set_capital(capital_id, &capital);
+        let msg = capital.intents_msg(&token_id);
+
+        ext_ft::ext(token_id.clone())
+            .with_static_gas(self.config.gas_ft_transfer_call)
+            .with_attached_deposit(NearToken::from_yoctonear(1))
+            .ft_transfer_call(self.config.intents_contract_id.clone(), amount, None, msg)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_RESOLVE_REDUCE_POSITION)
+                    .on_reduce_position(capital_id, token_id, amount),
+            )
+    }
+
+    #[private]
+    pub fn on_reduce_position(
+        &mut self,
+        capital_id: u64,
+        token_id: AccountId,
+        amount: U128,
+    ) -> U128 {
+        // ft_transfer_call resolves to the amount the receiver kept, the rest was refunded
+        let used = match env::promise_result(0) {
+            PromiseResult::Successful(value) => serde_json::from_slice::<U128>(&value)
+                .map(|used| used.0.min(amount.0))
+                .unwrap_or(0),
+            PromiseResult::Failed => 0,
+        };
+
+        let mut capital = self.internal_get_capital(capital_id);
+        capital.pending_reductions -= 1;
+        let refunded = amount.0 - used;
+        if refunded > 0 {
+            // the allocation stays active until all reductions resolve
+            let position = capital
+                .positions
+                .iter_mut()
+                .find(|position| position.token_id == token_id)
+                .unwrap();
+            position.amount += refunded;
+        }
+        if used > 0 {
+            match capital
+                .withdrawn
+                .iter_mut()
+                .find(|w| w.token_id == token_id)
+            {
+                Some(withdrawn) => withdrawn.amount += used,
+                None => capital.withdrawn.push(AssetPosition {
+                    token_id: token_id.clone(),
+                    amount: used,
+                }),
+            }
+        }
+        let initial_storage = env::storage_usage();
+        self.internal_set_capital(capital_id, &capital);
+        self.internal_track_storage(&capital.storage_payer_id(), initial_storage);
+
+        if used > 0 {
+            self.internal_record_position_change(
+                capital_id,
+                &capital.storage_payer_id(),
+                PositionChangeKind::Reduced,
+                token_id.clone(),
+                used,
+            );
+            VaultEvent::PositionReduced(PositionReducedEvent {
+                capital_id,
+                token_id,
+                amount: U128::from(used),
+            })
+            .emit();
+        }
+
+        U128::from(used)
+    }
+
+    pub fn get_position_history(
+        &self,
+        capital_id: u64,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<PositionChange> {
+        let Some(history) = self.position_history.get(&capital_id) else {
+            return vec![];
+        };
+        history
+            .iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(page_limit(limit) as usize)
+            .collect()
+    }
+}
+
+impl Contract {
+    pub(crate) fn internal_record_position_change(
+        &mut self,
+        capital_id: u64,
+        payer_id: &AccountId,
+        kind: PositionChangeKind,
+        token_id: AccountId,
+        amount: Balance,
+    ) {
+        let initial_storage = env::storage_usage();
+        let mut history = self
+            .position_history
+            .get(&capital_id)
+            .unwrap_or_else(|| Vector::new(position_history_prefix(capital_id)));
+        history.push(&PositionChange {
+            kind,
+            token_id,
+            amount: U128::from(amount),
+            timestamp: env::block_timestamp_ms(),
+        });
+        self.position_history.insert(&capital_id, &history);
+        self.internal_track_storage(payer_id, initial_storage);
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_logs};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::{get_context, set_promise_results};
+
+    // accounts(1) runs the agent and owns an allocation holding 1000 of accounts(4) tokens
+    fn get_contract() -> (Contract, u64) {
+        testing_env!(get_context(accounts(0)).build());
+        let mut contract = Contract::new(
+            "agent.near".to_string(),
+            accounts(1),
+            accounts(0),
+            None,
+            None,
+        );
+        contract.internal_storage_deposit(&accounts(1), NearToken::from_near(1).as_yoctonear());
+        contract.grant_role(accounts(1), Role::Operator);
+
+        testing_env!(get_context(accounts(1)).build());
+        let key = TwitterNearAccount {
+            twitter_id: 42,
+            near_account_id: accounts(1),
+        };
+        let token_id = AccountId::from_str(DEFAULT_USDC_CONTRACT_ID).unwrap();
+        contract.internal_set_balance(&key, &token_id, 1_000);
+        let funding = vec![FundingSource {
+            twitter_id: U128(42),
+            near_account_id: accounts(1),
+            amount: U128(1_000),
+        }];
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, None);
+        add_capital(&mut contract, capital_id, 1_000);
+        (contract, capital_id)
+    }
+
+    fn add_capital(contract: &mut Contract, capital_id: u64, amount: u128) {
+        testing_env!(get_context(accounts(4)).build());
+        let msg = format!(r#"{{"AddCapital":{{"capital_id":{}}}}}"#, capital_id);
+        contract.ft_on_transfer(accounts(1), U128(amount), msg);
+    }
+
+    fn reduce(contract: &mut Contract, capital_id: u64, amount: u128) {
+        testing_env!(get_context(accounts(1))
+            .prepaid_gas(Gas::from_tgas(300))
+            .build());
+        contract.reduce_position(capital_id, accounts(4), U128(amount));
+    }
+
+    #[test]
+    fn test_repeated_add_capital_is_merged() {
+        let (mut contract, capital_id) = get_contract();
+        add_capital(&mut contract, capital_id, 500);
+
+        let capital = contract.get_capital(capital_id);
+        assert_eq!(capital.positions.len(), 1);
+        assert_eq!(capital.positions[0].amount, 1_500);
+
+        let history = contract.get_position_history(capital_id, None, None);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].kind, PositionChangeKind::Added);
+        assert_eq!(history[1].amount.0, 500);
+    }
+
+    #[test]
+    fn test_reduce_position() {
+        let (mut contract, capital_id) = get_contract();
+        reduce(&mut contract, capital_id, 400);
+        assert_eq!(contract.get_capital(capital_id).positions[0].amount, 600);
+
+        set_promise_results(vec![PromiseResult::Successful(
+            serde_json::to_vec(&U128(400)).unwrap(),
+        )]);
+        assert_eq!(
+            contract
+                .on_reduce_position(capital_id, accounts(4), U128(400))
+                .0,
+            400
+        );
+        assert!(get_logs()[0].contains("\"event\":\"position_reduced\""));
+
+        let capital = contract.get_capital(capital_id);
+        assert_eq!(capital.positions[0].amount, 600);
+        assert_eq!(capital.withdrawn[0].amount, 400);
+        let history = contract.get_position_history(capital_id, Some(1), None);
+        assert_eq!(history[0].kind, PositionChangeKind::Reduced);
+        assert_eq!(history[0].amount.0, 400);
+    }
+
+    #[test]
+    fn test_reduce_position_refunded() {
+        let (mut contract, capital_id) = get_contract();
+        reduce(&mut contract, capital_id, 400);
+
+        set_promise_results(vec![PromiseResult::Successful(
+            serde_json::to_vec(&U128(100)).unwrap(),
+        )]);
+        contract.on_reduce_position(capital_id, accounts(4), U128(400));
+
+        let capital = contract.get_capital(capital_id);
+        assert_eq!(capital.positions[0].amount, 900);
+        assert_eq!(capital.withdrawn[0].amount, 100);
+
+        reduce(&mut contract, capital_id, 900);
+        set_promise_results(vec![PromiseResult::Failed]);
+        contract.on_reduce_position(capital_id, accounts(4), U128(900));
+        assert_eq!(contract.get_capital(capital_id).positions[0].amount, 900);
+        assert_eq!(
+            contract.get_position_history(capital_id, None, None).len(),
+            2
+        );
+    }
+
+    fn withdraw(contract: &mut Contract, capital_id: u64) {
+        testing_env!(get_context(accounts(1))
+            .block_timestamp(DEFAULT_LOCKTIME_IN_MS * 1_000_000)
+            .prepaid_gas(Gas::from_tgas(300))
+            .build());
+        contract.withdraw_capital(capital_id);
+    }
+
+    #[test]
+    fn test_reduce_refund_withdraw_settle() {
+        let (mut contract, capital_id) = get_contract();
+        reduce(&mut contract, capital_id, 400);
+        assert_eq!(contract.get_capital(capital_id).pending_reductions, 1);
+
+        set_promise_results(vec![PromiseResult::Successful(
+            serde_json::to_vec(&U128(100)).unwrap(),
+        )]);
+        contract.on_reduce_position(capital_id, accounts(4), U128(400));
+        assert_eq!(contract.get_capital(capital_id).pending_reductions, 0);
+
+        // the refunded 300 are withdrawn with the rest of the position
+        withdraw(&mut contract, capital_id);
+        set_promise_results(vec![PromiseResult::Successful(
+            serde_json::to_vec(&U128(900)).unwrap(),
+        )]);
+        contract.on_withdraw_capital(
+            capital_id,
+            vec![WithdrawalLeg {
+                position_index: 0,
+                amount: U128(900),
+            }],
+        );
+        testing_env!(get_context(accounts(1)).build());
+        contract.set_capital_exit_value(capital_id, U128(1_100), None);
+
+        let capital = contract.get_capital(capital_id);
+        assert!(capital.status == CapitalAllocationStatus::Withdrawn);
+        assert_eq!(capital.positions[0].amount, 0);
+        assert_eq!(capital.withdrawn[0].amount, 1_000);
+        assert_eq!(capital.pnl_bps.unwrap().0, 1_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Capital Allocation has pending position reductions")]
+    fn test_withdraw_with_pending_reduction() {
+        let (mut contract, capital_id) = get_contract();
+        reduce(&mut contract, capital_id, 400);
+        withdraw(&mut contract, capital_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "Capital Allocation has pending position reductions")]
+    fn test_exit_early_with_pending_reduction() {
+        let (mut contract, capital_id) = get_contract();
+        reduce(&mut contract, capital_id, 400);
+        contract.exit_capital_early(capital_id, EarlyExitReason::StopLoss);
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough amount in position")]
+    fn test_reduce_position_above_amount() {
+        let (mut contract, capital_id) = get_contract();
+        reduce(&mut contract, capital_id, 1_001);
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires Agent role")]
+    fn test_reduce_position_not_agent() {
+        let (mut contract, capital_id) = get_contract();
+        testing_env!(get_context(accounts(3))
+            .prepaid_gas(Gas::from_tgas(300))
+            .build());
+        contract.reduce_position(capital_id, accounts(4), U128(100));
+    }
+}
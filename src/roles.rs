@@ -0,0 +1,195 @@
+use crate::*;
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Role {
+    // manages roles and contract configuration
+    Owner,
+    // runs the AI agent, creates allocations and adds capital
+    Agent,
+    // settles allocations, reports exit values and pays out user balances
+    Operator,
+    Pauser,
+    Guardian,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn grant_role(&mut self, account_id: AccountId, role: Role) {
+        self.require_role(Role::Owner);
+        self.internal_grant_role(&account_id, role);
+    }
+
+    pub fn revoke_role(&mut self, account_id: AccountId, role: Role) {
+        self.require_role(Role::Owner);
+        if role == Role::Owner {
+            assert!(
+                self.get_role_members(Role::Owner).len() > 1,
+                "Can't revoke the last owner"
+            );
+        }
+        self.internal_revoke_role(&account_id, role);
+    }
+
+    pub fn has_role(&self, account_id: AccountId, role: Role) -> bool {
+        self.internal_has_role(&account_id, role)
+    }
+
+    pub fn get_roles(&self, account_id: AccountId) -> Vec<Role> {
+        self.roles.get(&account_id).unwrap_or_default()
+    }
+
+    pub fn get_role_members(&self, role: Role) -> Vec<AccountId> {
+        self.roles
+            .iter()
+            .filter(|(_, roles)| roles.contains(&role))
+            .map(|(account_id, _)| account_id)
+            .collect()
+    }
+}
+
+impl Contract {
+    pub(crate) fn require_role(&self, role: Role) {
+        assert!(
+            self.internal_has_role(&env::predecessor_account_id(), role),
+            "Requires {:?} role",
+            role
+        );
+    }
+
+    pub(crate) fn internal_has_role(&self, account_id: &AccountId, role: Role) -> bool {
+        self.roles
+            .get(account_id)
+            .is_some_and(|roles| roles.contains(&role))
+    }
+
+    pub(crate) fn internal_grant_role(&mut self, account_id: &AccountId, role: Role) {
+        let mut roles = self.roles.get(account_id).unwrap_or_default();
+        if !roles.contains(&role) {
+            roles.push(role);
+            self.roles.insert(account_id, &roles);
+        }
+    }
+
+    pub(crate) fn internal_revoke_role(&mut self, account_id: &AccountId, role: Role) {
+        let mut roles = self.roles.get(account_id).unwrap_or_default();
+        roles.retain(|r| *r != role);
+        if roles.is_empty() {
+            self.roles.remove(account_id);
+        } else {
+            self.roles.insert(account_id, &roles);
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::{fund_allocation, get_context};
+
+    // deployed and initialized by accounts(0), accounts(1) runs the agent
+    fn get_contract() -> Contract {
+        testing_env!(get_context(accounts(0)).build());
+        let mut contract = Contract::new(
+            "agent.near".to_string(),
+            accounts(1),
+            accounts(0),
+            None,
+            None,
+        );
+        contract.internal_storage_deposit(&accounts(1), NearToken::from_near(1).as_yoctonear());
+        contract.internal_storage_deposit(&accounts(3), NearToken::from_near(1).as_yoctonear());
+        contract
+    }
+
+    #[test]
+    fn test_initial_roles() {
+        let contract = get_contract();
+        assert_eq!(contract.get_roles(accounts(0)), vec![Role::Owner]);
+        assert_eq!(contract.get_roles(accounts(1)), vec![Role::Agent]);
+        assert_eq!(contract.get_role_members(Role::Owner), vec![accounts(0)]);
+        assert!(contract.get_role_members(Role::Operator).is_empty());
+    }
+
+    #[test]
+    fn test_grant_and_revoke_role() {
+        let mut contract = get_contract();
+        contract.grant_role(accounts(2), Role::Operator);
+        contract.grant_role(accounts(2), Role::Pauser);
+        contract.grant_role(accounts(2), Role::Operator);
+        assert_eq!(
+            contract.get_roles(accounts(2)),
+            vec![Role::Operator, Role::Pauser]
+        );
+        assert!(contract.has_role(accounts(2), Role::Operator));
+
+        contract.revoke_role(accounts(2), Role::Operator);
+        assert!(!contract.has_role(accounts(2), Role::Operator));
+        assert_eq!(contract.get_role_members(Role::Pauser), vec![accounts(2)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires Owner role")]
+    fn test_grant_role_not_owner() {
+        let mut contract = get_contract();
+        testing_env!(get_context(accounts(1)).build());
+        contract.grant_role(accounts(1), Role::Operator);
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't revoke the last owner")]
+    fn test_revoke_last_owner() {
+        let mut contract = get_contract();
+        contract.revoke_role(accounts(0), Role::Owner);
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires Operator role")]
+    fn test_agent_cannot_withdraw_user_funds() {
+        let mut contract = get_contract();
+        testing_env!(get_context(accounts(1)).build());
+        contract.withdraw_usdc(U128(1), accounts(3), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires Operator role")]
+    fn test_agent_cannot_set_exit_value() {
+        let mut contract = get_contract();
+        testing_env!(get_context(accounts(1)).build());
+        let funding = fund_allocation(&mut contract, 1000);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, Some(0));
+        contract.set_capital_exit_value(capital_id, U128(1000), None);
+    }
+
+    #[test]
+    fn test_operator_settles_allocation() {
+        let mut contract = get_contract();
+        contract.grant_role(accounts(2), Role::Operator);
+
+        testing_env!(get_context(accounts(1)).build());
+        let funding = fund_allocation(&mut contract, 1000);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, Some(0));
+
+        testing_env!(get_context(accounts(2)).build());
+        contract.withdraw_capital(capital_id);
+        contract.set_capital_exit_value(capital_id, U128(1100), None);
+        assert_eq!(
+            contract.get_capital(capital_id).exit_value.unwrap().amount,
+            1100
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires Agent role")]
+    fn test_operator_cannot_create_allocation() {
+        let mut contract = get_contract();
+        contract.grant_role(accounts(2), Role::Operator);
+
+        testing_env!(get_context(accounts(2)).build());
+        let funding = fund_allocation(&mut contract, 1000);
+        contract.create_capital_allocation(accounts(1), funding, None, Some(0));
+    }
+}
@@ -0,0 +1,220 @@
+use crate::*;
+use near_sdk::IntoStorageKey;
+
+const MAX_OPEN_SEASONS: usize = 10;
+
+#[near(serializers = [borsh])]
+pub struct Season {
+    pub name: String,
+    pub start_timestamp: Timestamp,
+    pub end_timestamp: Timestamp,
+    // allocations settled while the season is open with `exit_timestamp` within it
+    pub leaderboard: Leaderboard,
+    pub closed: bool,
+}
+
+#[near(serializers = [json])]
+pub struct SeasonOutput {
+    pub season_id: u64,
+    pub name: String,
+    pub start_timestamp: Timestamp,
+    pub end_timestamp: Timestamp,
+    pub closed: bool,
+}
+
+fn season_leaderboard_prefix(season_id: u64) -> Vec<u8> {
+    let mut prefix = StorageKey::SeasonLeaderboards.into_storage_key();
+    prefix.extend(season_id.to_le_bytes());
+    prefix
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn create_season(
+        &mut self,
+        name: String,
+        start_timestamp: Timestamp,
+        end_timestamp: Timestamp,
+    ) -> u64 {
+        self.require_role(Role::Owner);
+        assert!(
+            start_timestamp < end_timestamp,
+            "Season must end after it starts"
+        );
+        assert!(
+            self.open_seasons.len() < MAX_OPEN_SEASONS,
+            "Too many open seasons"
+        );
+
+        let season_id = self.seasons.len();
+        self.seasons.push(&Season {
+            name: name.clone(),
+            start_timestamp,
+            end_timestamp,
+            leaderboard: Leaderboard::new(season_leaderboard_prefix(season_id)),
+            closed: false,
+        });
+        self.open_seasons.push(season_id);
+
+        VaultEvent::SeasonCreated(SeasonCreatedEvent {
+            season_id,
+            name,
+            start_timestamp,
+            end_timestamp,
+        })
+        .emit();
+
+        season_id
+    }
+
+    // freezes the season leaderboard, later exit values don't change it
+    pub fn close_season(&mut self, season_id: u64) {
+        self.require_role(Role::Owner);
+        let mut season = self.internal_get_season(season_id);
+        assert!(!season.closed, "Season is already closed");
+        assert!(
+            env::block_timestamp_ms() >= season.end_timestamp,
+            "Season has not ended yet"
+        );
+
+        season.closed = true;
+        self.seasons.replace(season_id, &season);
+        self.open_seasons.retain(|id| *id != season_id);
+
+        VaultEvent::SeasonClosed(SeasonClosedEvent {
+            season_id,
+            leaderboard: season.leaderboard.to_vec(),
+        })
+        .emit();
+    }
+
+    pub fn get_season_leaderboard(&self, season_id: u64) -> Vec<LeaderboardItem> {
+        self.internal_get_season(season_id).leaderboard.to_vec()
+    }
+
+    pub fn list_seasons(&self) -> Vec<SeasonOutput> {
+        self.seasons
+            .iter()
+            .enumerate()
+            .map(|(season_id, season)| SeasonOutput {
+                season_id: season_id as u64,
+                name: season.name,
+                start_timestamp: season.start_timestamp,
+                end_timestamp: season.end_timestamp,
+                closed: season.closed,
+            })
+            .collect()
+    }
+}
+
+impl Contract {
+    fn internal_get_season(&self, season_id: u64) -> Season {
+        self.seasons.get(season_id).expect("Season not found")
+    }
+
+    // ranks a settled allocation in every open season its `exit_timestamp` falls into
+    pub(crate) fn internal_add_to_seasons(&mut self, capital_id: u64, capital: &CapitalAllocation) {
+        let Some(pnl_bps) = capital.pnl_bps else {
+            return;
+        };
+        // season boards hold at most `leaderboard_length` items, the vault pays for them
+        for season_id in self.open_seasons.clone() {
+            let mut season = self.internal_get_season(season_id);
+            if (season.start_timestamp..season.end_timestamp).contains(&capital.exit_timestamp) {
+                let ranked = season.leaderboard.add_item(
+                    LeaderboardItem {
+                        near_account_id: capital.owner_id.clone(),
+                        pnl_bps,
+                        capital_id,
+                    },
+                    self.leaderboard_length,
+                );
+                if ranked {
+                    self.seasons.replace(season_id, &season);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_logs};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::{get_context, settle};
+
+    const HOUR_MS: u64 = 3_600_000;
+
+    // accounts(0) owns the vault, accounts(1) runs the agent
+    fn get_contract() -> Contract {
+        testing_env!(get_context(accounts(0)).build());
+        let mut contract = Contract::new(
+            "agent.near".to_string(),
+            accounts(1),
+            accounts(0),
+            None,
+            None,
+        );
+        contract.internal_storage_deposit(&accounts(1), NearToken::from_near(1).as_yoctonear());
+        contract.internal_storage_deposit(&accounts(3), NearToken::from_near(1).as_yoctonear());
+        contract.grant_role(accounts(1), Role::Operator);
+        contract
+    }
+
+    #[test]
+    fn test_season_leaderboard() {
+        let mut contract = get_contract();
+        let season_id = contract.create_season("Season 1".to_string(), HOUR_MS, 2 * HOUR_MS);
+        assert!(get_logs()[0].contains("\"event\":\"season_created\""));
+
+        settle(&mut contract, accounts(3), 1_000, 1_500, HOUR_MS / 2);
+        let in_season = settle(&mut contract, accounts(3), 1_000, 1_100, HOUR_MS + 1);
+        settle(&mut contract, accounts(3), 1_000, 1_200, 2 * HOUR_MS);
+
+        let leaderboard = contract.get_season_leaderboard(season_id);
+        assert_eq!(leaderboard.len(), 1);
+        assert_eq!(leaderboard[0].capital_id, in_season);
+        assert_eq!(leaderboard[0].pnl_bps.0, 1_000);
+        assert_eq!(contract.get_leaderboard().len(), 3);
+    }
+
+    #[test]
+    fn test_close_season() {
+        let mut contract = get_contract();
+        let season_id = contract.create_season("Season 1".to_string(), 0, 2 * HOUR_MS);
+        settle(&mut contract, accounts(3), 1_000, 1_100, HOUR_MS);
+
+        testing_env!(get_context(accounts(0))
+            .block_timestamp(2 * HOUR_MS * 1_000_000)
+            .build());
+        contract.close_season(season_id);
+        assert!(get_logs()[0].contains("\"event\":\"season_closed\""));
+
+        // allocations settled after closing don't change the snapshot
+        settle(&mut contract, accounts(3), 1_000, 1_300, HOUR_MS);
+        assert_eq!(contract.get_season_leaderboard(season_id).len(), 1);
+
+        let seasons = contract.list_seasons();
+        assert_eq!(seasons.len(), 1);
+        assert_eq!(seasons[0].name, "Season 1");
+        assert!(seasons[0].closed);
+    }
+
+    #[test]
+    #[should_panic(expected = "Season has not ended yet")]
+    fn test_close_season_before_end() {
+        let mut contract = get_contract();
+        let season_id = contract.create_season("Season 1".to_string(), 0, HOUR_MS);
+        contract.close_season(season_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires Owner role")]
+    fn test_create_season_not_owner() {
+        let mut contract = get_contract();
+        testing_env!(get_context(accounts(1)).build());
+        contract.create_season("Season 1".to_string(), 0, HOUR_MS);
+    }
+}
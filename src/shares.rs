@@ -0,0 +1,463 @@
+use crate::*;
+use near_contract_standards::fungible_token::core::FungibleTokenCore;
+use near_contract_standards::fungible_token::events::{FtBurn, FtMint};
+use near_contract_standards::fungible_token::metadata::{
+    FungibleTokenMetadata, FungibleTokenMetadataProvider, FT_METADATA_SPEC,
+};
+use near_contract_standards::fungible_token::resolver::FungibleTokenResolver;
+use near_sdk::assert_one_yocto;
+
+const SHARE_TOKEN_NAME: &str = "DeFAI Vault Share";
+const SHARE_TOKEN_SYMBOL: &str = "DVS";
+
+// ledger balance of the share pool, allocations are funded from it with this key
+pub(crate) fn share_pool_key() -> TwitterNearAccount {
+    TwitterNearAccount {
+        twitter_id: 0,
+        near_account_id: env::current_account_id(),
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenCore for Contract {
+    #[payable]
+    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>) {
+        self.shares.ft_transfer(receiver_id, amount, memo)
+    }
+
+    #[payable]
+    fn ft_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        self.shares.ft_transfer_call(receiver_id, amount, memo, msg)
+    }
+
+    fn ft_total_supply(&self) -> U128 {
+        self.shares.ft_total_supply()
+    }
+
+    fn ft_balance_of(&self, account_id: AccountId) -> U128 {
+        self.shares.ft_balance_of(account_id)
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenResolver for Contract {
+    #[private]
+    fn ft_resolve_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> U128 {
+        let (used_amount, _) =
+            self.shares
+                .internal_ft_resolve_transfer(&sender_id, receiver_id, amount);
+        used_amount.into()
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenMetadataProvider for Contract {
+    fn ft_metadata(&self) -> FungibleTokenMetadata {
+        FungibleTokenMetadata {
+            spec: FT_METADATA_SPEC.to_string(),
+            name: SHARE_TOKEN_NAME.to_string(),
+            symbol: SHARE_TOKEN_SYMBOL.to_string(),
+            icon: None,
+            reference: None,
+            reference_hash: None,
+            decimals: USDC_DECIMALS,
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    // burns the caller's shares and pays out their part of the NAV from the idle share pool
+    // share holders have no other exit, so like `emergency_withdraw` it ignores pauses in emergency mode
+    #[payable]
+    pub fn redeem_shares(&mut self, shares: U128) -> Promise {
+        assert_one_yocto();
+        if !self.emergency_mode {
+            self.assert_not_paused(PausableOperation::Withdrawals);
+        }
+        assert!(shares.0 > 0, "Shares amount must be positive");
+        let account_id = env::predecessor_account_id();
+        let amount = mul_div(
+            shares.0,
+            self.internal_share_nav(),
+            self.shares.total_supply,
+        );
+        assert!(amount > 0, "Nothing to redeem");
+        assert!(
+            amount <= self.internal_get_balance(&share_pool_key(), &self.share_token_id()),
+            "Not enough idle liquidity to redeem shares"
+        );
+
+        self.shares.internal_withdraw(&account_id, shares.0);
+        self.internal_withdraw(&share_pool_key(), &self.share_token_id(), amount);
+        FtBurn {
+            owner_id: &account_id,
+            amount: shares,
+            memo: Some("redeem"),
+        }
+        .emit();
+
+        ext_ft::ext(self.share_token_id())
+            .with_static_gas(self.config.gas_ft_transfer)
+            .with_attached_deposit(NearToken::from_yoctonear(1))
+            .ft_transfer(account_id.clone(), U128::from(amount), None)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_RESOLVE_WITHDRAW)
+                    .on_redeem_shares(account_id, shares, U128::from(amount)),
+            )
+    }
+
+    #[private]
+    // mints the shares back at the old price if the payout failed
+    pub fn on_redeem_shares(&mut self, account_id: AccountId, shares: U128, amount: U128) -> bool {
+        let success = is_promise_success();
+
+        if !success {
+            // the account could have unregistered after its shares were burned
+            if self.shares.accounts.get(&account_id).is_none() {
+                self.shares.internal_register_account(&account_id);
+            }
+            self.shares.internal_deposit(&account_id, shares.0);
+            let pool = share_pool_key();
+            let balance = self.internal_get_balance(&pool, &self.share_token_id());
+            self.internal_set_balance(&pool, &self.share_token_id(), balance + amount.0);
+            FtMint {
+                owner_id: &account_id,
+                amount: shares,
+                memo: Some("refund"),
+            }
+            .emit();
+        }
+
+        success
+    }
+
+    // idle share pool balance plus the entry value of unsettled allocations it funded
+    pub fn get_share_nav(&self) -> U128 {
+        U128::from(self.internal_share_nav())
+    }
+
+    // value of one whole share in USDC
+    pub fn get_share_price(&self) -> U128 {
+        let one_share = 10u128.pow(USDC_DECIMALS as u32);
+        if self.shares.total_supply == 0 {
+            return U128::from(one_share);
+        }
+        U128::from(mul_div(
+            one_share,
+            self.internal_share_nav(),
+            self.shares.total_supply,
+        ))
+    }
+}
+
+impl Contract {
+    // shares are minted for and redeemed in USDC
+    pub(crate) fn share_token_id(&self) -> AccountId {
+        self.usdc_contract_id()
+    }
+
+    pub(crate) fn internal_share_nav(&self) -> Balance {
+        self.internal_get_balance(&share_pool_key(), &self.share_token_id()) + self.shares_allocated
+    }
+
+    // shares `amount` buys at the current price, without minting them
+    pub(crate) fn check_mint_shares(
+        &self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+        amount: Balance,
+    ) -> Result<Balance, DepositRejectReason> {
+        if *token_id != self.share_token_id() {
+            return Err(DepositRejectReason::NotShareToken);
+        }
+        let token = self
+            .tokens
+            .get(token_id)
+            .ok_or(DepositRejectReason::TokenNotWhitelisted)?;
+        if amount < token.min_deposit.0 {
+            return Err(DepositRejectReason::BelowMinDeposit);
+        }
+        if self.shares.accounts.get(account_id).is_none() {
+            return Err(DepositRejectReason::StorageNotRegistered);
+        }
+
+        let nav = self.internal_share_nav();
+        if self.shares.total_supply == 0 {
+            // proceeds of allocations funded by former holders would go to the new ones
+            if self.shares_allocated > 0 {
+                return Err(DepositRejectReason::SharePoolUnsettled);
+            }
+            // what's left in the pool is swept to the treasury on mint
+            return Ok(amount);
+        }
+        if nav == 0 {
+            return Err(DepositRejectReason::SharePoolDepleted);
+        }
+        match mul_div(amount, self.shares.total_supply, nav) {
+            0 => Err(DepositRejectReason::BelowMinDeposit),
+            shares => Ok(shares),
+        }
+    }
+
+    // moves the pool balance nobody holds shares for to the treasury
+    pub(crate) fn internal_sweep_share_pool(&mut self) {
+        if self.shares.total_supply > 0 {
+            return;
+        }
+        let pool = share_pool_key();
+        let token_id = self.share_token_id();
+        let residual = self.internal_get_balance(&pool, &token_id);
+        if residual > 0 {
+            self.internal_set_balance(&pool, &token_id, 0);
+            self.internal_credit_treasury(&token_id, residual);
+        }
+    }
+
+    pub(crate) fn internal_mint_shares(
+        &mut self,
+        account_id: &AccountId,
+        amount: Balance,
+        shares: Balance,
+    ) {
+        self.internal_sweep_share_pool();
+        let pool = share_pool_key();
+        let balance = self.internal_get_balance(&pool, &self.share_token_id());
+        self.internal_set_balance(&pool, &self.share_token_id(), balance + amount);
+        self.shares.internal_deposit(account_id, shares);
+        FtMint {
+            owner_id: account_id,
+            amount: U128::from(shares),
+            memo: Some("deposit"),
+        }
+        .emit();
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::{get_context, set_promise_results};
+
+    // accounts(1) runs the agent and settles allocations, accounts(2) and accounts(3) hold shares
+    fn get_contract() -> Contract {
+        testing_env!(get_context(accounts(0)).build());
+        let mut contract = Contract::new(
+            "agent.near".to_string(),
+            accounts(1),
+            accounts(0),
+            None,
+            None,
+        );
+        contract.internal_storage_deposit(&accounts(1), NearToken::from_near(1).as_yoctonear());
+        contract.internal_storage_deposit(&accounts(2), NearToken::from_near(1).as_yoctonear());
+        contract.internal_storage_deposit(&accounts(3), NearToken::from_near(1).as_yoctonear());
+        contract.grant_role(accounts(1), Role::Operator);
+        contract
+    }
+
+    fn mint_shares(contract: &mut Contract, account_id: AccountId, amount: u128) -> u128 {
+        testing_env!(get_context(contract.share_token_id()).build());
+        let msg = r#""MintShares""#.to_string();
+        match contract.ft_on_transfer(account_id, U128(amount), msg) {
+            PromiseOrValue::Value(unused) => unused.0,
+            PromiseOrValue::Promise(_) => panic!("Expected a value"),
+        }
+    }
+
+    // allocation funded from the share pool, settled at `exit_amount`
+    fn settle_pool_allocation(contract: &mut Contract, entry_amount: u128, exit_amount: u128) {
+        testing_env!(get_context(accounts(1)).build());
+        let funding = vec![FundingSource {
+            twitter_id: U128(0),
+            near_account_id: accounts(0),
+            amount: U128(entry_amount),
+        }];
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, Some(0));
+        contract.withdraw_capital(capital_id);
+        contract.set_capital_exit_value(capital_id, U128(exit_amount), None);
+    }
+
+    #[test]
+    fn test_mint_shares_at_price() {
+        let mut contract = get_contract();
+        assert_eq!(contract.get_share_price().0, 1_000_000);
+
+        assert_eq!(mint_shares(&mut contract, accounts(2), 1_000_000), 0);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 1_000_000);
+        assert_eq!(contract.get_share_nav().0, 1_000_000);
+
+        // profits raise the price of the existing shares
+        settle_pool_allocation(&mut contract, 400_000, 600_000);
+        assert_eq!(contract.get_share_nav().0, 1_200_000);
+        assert_eq!(contract.get_share_price().0, 1_200_000);
+
+        assert_eq!(mint_shares(&mut contract, accounts(3), 600_000), 0);
+        assert_eq!(contract.ft_balance_of(accounts(3)).0, 500_000);
+        assert_eq!(contract.ft_total_supply().0, 1_500_000);
+    }
+
+    #[test]
+    fn test_mint_shares_sweeps_residual() {
+        let mut contract = get_contract();
+        let usdc = contract.share_token_id();
+        contract.internal_set_balance(&share_pool_key(), &usdc, 300_000);
+
+        // nobody holds a claim on the residual, the first depositor doesn't get it
+        assert_eq!(mint_shares(&mut contract, accounts(2), 1_000_000), 0);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 1_000_000);
+        assert_eq!(contract.get_share_nav().0, 1_000_000);
+        assert_eq!(contract.get_treasury_balance(usdc).0, 300_000);
+    }
+
+    #[test]
+    fn test_mint_shares_while_pool_unsettled() {
+        let mut contract = get_contract();
+        contract.shares_allocated = 500_000;
+
+        assert_eq!(
+            mint_shares(&mut contract, accounts(2), 1_000_000),
+            1_000_000
+        );
+        assert_eq!(contract.ft_total_supply().0, 0);
+    }
+
+    #[test]
+    fn test_mint_shares_rejected() {
+        let mut contract = get_contract();
+
+        testing_env!(get_context(accounts(4)).build());
+        let msg = r#""MintShares""#.to_string();
+        match contract.ft_on_transfer(accounts(2), U128(1_000_000), msg) {
+            PromiseOrValue::Value(unused) => assert_eq!(unused.0, 1_000_000),
+            PromiseOrValue::Promise(_) => panic!("Expected a value"),
+        }
+
+        // accounts(4) is not registered for storage
+        assert_eq!(
+            mint_shares(&mut contract, accounts(4), 1_000_000),
+            1_000_000
+        );
+        assert_eq!(mint_shares(&mut contract, accounts(2), 1), 1);
+        assert_eq!(contract.ft_total_supply().0, 0);
+    }
+
+    #[test]
+    fn test_transfer_and_redeem_shares() {
+        let mut contract = get_contract();
+        mint_shares(&mut contract, accounts(2), 1_000_000);
+
+        testing_env!(get_context(accounts(2))
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.ft_transfer(accounts(3), U128(400_000), None);
+        assert_eq!(contract.ft_balance_of(accounts(3)).0, 400_000);
+
+        settle_pool_allocation(&mut contract, 500_000, 400_000);
+        assert_eq!(contract.get_share_nav().0, 900_000);
+
+        testing_env!(get_context(accounts(3))
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.redeem_shares(U128(400_000));
+        assert_eq!(contract.ft_balance_of(accounts(3)).0, 0);
+        assert_eq!(contract.get_share_nav().0, 540_000);
+
+        set_promise_results(vec![PromiseResult::Successful(vec![])]);
+        assert!(contract.on_redeem_shares(accounts(3), U128(400_000), U128(360_000)));
+        assert_eq!(contract.get_share_nav().0, 540_000);
+    }
+
+    #[test]
+    fn test_redeem_shares_in_emergency_while_paused() {
+        let mut contract = get_contract();
+        mint_shares(&mut contract, accounts(2), 1_000_000);
+        testing_env!(get_context(accounts(0)).build());
+        contract.grant_role(accounts(4), Role::Guardian);
+        contract.grant_role(accounts(4), Role::Pauser);
+        testing_env!(get_context(accounts(4)).build());
+        contract.set_paused(PausableOperation::Withdrawals, true);
+        contract.set_emergency_mode(true);
+
+        testing_env!(get_context(accounts(2))
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.redeem_shares(U128(1_000_000));
+        assert_eq!(contract.ft_total_supply().0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Withdrawals are paused")]
+    fn test_redeem_shares_while_paused() {
+        let mut contract = get_contract();
+        mint_shares(&mut contract, accounts(2), 1_000_000);
+        testing_env!(get_context(accounts(0)).build());
+        contract.grant_role(accounts(4), Role::Pauser);
+        testing_env!(get_context(accounts(4)).build());
+        contract.set_paused(PausableOperation::Withdrawals, true);
+
+        testing_env!(get_context(accounts(2))
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.redeem_shares(U128(1_000_000));
+    }
+
+    #[test]
+    fn test_redeem_shares_failure() {
+        let mut contract = get_contract();
+        mint_shares(&mut contract, accounts(2), 1_000_000);
+
+        testing_env!(get_context(accounts(2))
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.redeem_shares(U128(250_000));
+
+        set_promise_results(vec![PromiseResult::Failed]);
+        assert!(!contract.on_redeem_shares(accounts(2), U128(250_000), U128(250_000)));
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 1_000_000);
+        assert_eq!(contract.get_share_nav().0, 1_000_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough idle liquidity to redeem shares")]
+    fn test_redeem_shares_locked_in_allocation() {
+        let mut contract = get_contract();
+        mint_shares(&mut contract, accounts(2), 1_000_000);
+
+        testing_env!(get_context(accounts(1)).build());
+        let funding = vec![FundingSource {
+            twitter_id: U128(0),
+            near_account_id: accounts(0),
+            amount: U128(800_000),
+        }];
+        contract.create_capital_allocation(accounts(1), funding, None, None);
+
+        testing_env!(get_context(accounts(2))
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.redeem_shares(U128(500_000));
+    }
+
+    #[test]
+    fn test_ft_metadata() {
+        let contract = get_contract();
+        let metadata = contract.ft_metadata();
+        assert_eq!(metadata.symbol, SHARE_TOKEN_SYMBOL);
+        assert_eq!(metadata.decimals, USDC_DECIMALS);
+    }
+}
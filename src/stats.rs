@@ -0,0 +1,246 @@
+use crate::*;
+
+// settled allocations of an owner valued in one token
+#[near(serializers = [borsh])]
+#[derive(Default)]
+pub struct AccountStats {
+    pub allocations: u64,
+    pub wins: u64,
+    pub losses: u64,
+    pub cumulative_entry: Balance,
+    pub cumulative_exit: Balance,
+    pub best_pnl_bps: Option<i128>,
+    pub worst_pnl_bps: Option<i128>,
+    // allocations with an empty entry value have no return and are left out of the averages
+    scored_allocations: u64,
+    pnl_bps_sum: i128,
+    // returns weighted by the time the capital was locked, in bps * ms
+    weighted_pnl_bps_sum: i128,
+    weighted_ms: u128,
+    // the allocation the account's leaderboard item was last ranked with
+    last_capital_id: Option<u64>,
+}
+
+#[near(serializers = [json])]
+pub struct AccountStatsOutput {
+    pub allocations: u64,
+    pub wins: u64,
+    pub losses: u64,
+    pub cumulative_entry: U128,
+    pub cumulative_exit: U128,
+    // return on the cumulative entry value
+    pub pnl_bps: Option<I128>,
+    pub best_pnl_bps: Option<I128>,
+    pub worst_pnl_bps: Option<I128>,
+    pub average_pnl_bps: Option<I128>,
+    pub time_weighted_pnl_bps: Option<I128>,
+}
+
+impl From<AccountStats> for AccountStatsOutput {
+    fn from(stats: AccountStats) -> Self {
+        Self {
+            allocations: stats.allocations,
+            wins: stats.wins,
+            losses: stats.losses,
+            cumulative_entry: U128::from(stats.cumulative_entry),
+            cumulative_exit: U128::from(stats.cumulative_exit),
+            pnl_bps: pnl_bps(stats.cumulative_entry, stats.cumulative_exit).map(I128::from),
+            best_pnl_bps: stats.best_pnl_bps.map(I128::from),
+            worst_pnl_bps: stats.worst_pnl_bps.map(I128::from),
+            average_pnl_bps: (stats.scored_allocations > 0)
+                .then(|| I128::from(stats.pnl_bps_sum / stats.scored_allocations as i128)),
+            time_weighted_pnl_bps: (stats.weighted_ms > 0)
+                .then(|| I128::from(stats.weighted_pnl_bps_sum / stats.weighted_ms as i128)),
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    // stats of allocations valued in `token_id`, USDC by default
+    pub fn get_account_stats(
+        &self,
+        account_id: AccountId,
+        token_id: Option<AccountId>,
+    ) -> AccountStatsOutput {
+        let token_id = token_id.unwrap_or(self.usdc_contract_id());
+        self.account_stats
+            .get(&(account_id, token_id))
+            .unwrap_or_default()
+            .into()
+    }
+
+    // accounts ranked by the return on their cumulative USDC entry value, top `leaderboard_length`
+    pub fn get_account_leaderboard(&self) -> Vec<LeaderboardItem> {
+        self.account_leaderboard
+            .page(0, self.leaderboard_length as u64)
+    }
+}
+
+impl Contract {
+    pub(crate) fn internal_update_account_stats(
+        &mut self,
+        capital_id: u64,
+        capital: &CapitalAllocation,
+    ) {
+        let exit_value = capital.exit_value.as_ref().unwrap();
+        let key = (capital.owner_id.clone(), exit_value.token_id.clone());
+        let mut stats = self.account_stats.get(&key).unwrap_or_default();
+        let previous_item = stats
+            .last_capital_id
+            .zip(pnl_bps(stats.cumulative_entry, stats.cumulative_exit));
+
+        stats.allocations += 1;
+        stats.cumulative_entry += capital.entry_value.amount;
+        stats.cumulative_exit += exit_value.amount;
+        if let Some(I128(pnl_bps)) = capital.pnl_bps {
+            match pnl_bps.cmp(&0) {
+                std::cmp::Ordering::Greater => stats.wins += 1,
+                std::cmp::Ordering::Less => stats.losses += 1,
+                std::cmp::Ordering::Equal => {}
+            }
+            stats.best_pnl_bps = Some(stats.best_pnl_bps.map_or(pnl_bps, |best| best.max(pnl_bps)));
+            stats.worst_pnl_bps = Some(
+                stats
+                    .worst_pnl_bps
+                    .map_or(pnl_bps, |worst| worst.min(pnl_bps)),
+            );
+            stats.scored_allocations += 1;
+            stats.pnl_bps_sum = stats.pnl_bps_sum.saturating_add(pnl_bps);
+
+            let locked_ms = env::block_timestamp_ms()
+                .min(capital.exit_timestamp)
+                .saturating_sub(capital.entry_timestamp);
+            stats.weighted_pnl_bps_sum = stats
+                .weighted_pnl_bps_sum
+                .saturating_add(pnl_bps.saturating_mul(locked_ms as i128));
+            stats.weighted_ms += locked_ms as u128;
+        }
+
+        let ranked_pnl_bps = pnl_bps(stats.cumulative_entry, stats.cumulative_exit)
+            .filter(|_| exit_value.token_id == self.config.usdc_contract_id);
+        if ranked_pnl_bps.is_some() {
+            stats.last_capital_id = Some(capital_id);
+        }
+        let initial_storage = env::storage_usage();
+        self.account_stats.insert(&key, &stats);
+        if let Some(pnl_bps) = ranked_pnl_bps {
+            // one item per account, moved on every settlement
+            if let Some((capital_id, pnl_bps)) = previous_item {
+                self.account_leaderboard.remove_item(&LeaderboardItem {
+                    near_account_id: capital.owner_id.clone(),
+                    pnl_bps: I128(pnl_bps),
+                    capital_id,
+                });
+            }
+            self.account_leaderboard.insert_item(LeaderboardItem {
+                near_account_id: capital.owner_id.clone(),
+                pnl_bps: I128(pnl_bps),
+                capital_id,
+            });
+        }
+        self.internal_track_storage(&capital.storage_payer_id(), initial_storage);
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::leaderboard::LeaderboardKind;
+    use crate::test_utils::{get_context, settle};
+
+    const HOUR_MS: u64 = 3_600_000;
+
+    // accounts(0) owns the vault, accounts(1) runs the agent, accounts(3) funds the allocations
+    fn get_contract() -> Contract {
+        testing_env!(get_context(accounts(0)).build());
+        let mut contract = Contract::new(
+            "agent.near".to_string(),
+            accounts(1),
+            accounts(0),
+            None,
+            None,
+        );
+        contract.internal_storage_deposit(&accounts(1), NearToken::from_near(1).as_yoctonear());
+        contract.internal_storage_deposit(&accounts(3), NearToken::from_near(1).as_yoctonear());
+        contract.internal_storage_deposit(&accounts(4), NearToken::from_near(1).as_yoctonear());
+        contract.grant_role(accounts(1), Role::Operator);
+        contract
+    }
+
+    #[test]
+    fn test_account_stats() {
+        let mut contract = get_contract();
+        settle(&mut contract, accounts(3), 1_000, 1_500, HOUR_MS);
+        settle(&mut contract, accounts(3), 3_000, 2_700, 3 * HOUR_MS);
+        settle(&mut contract, accounts(3), 1_000, 1_000, 0);
+
+        let stats = contract.get_account_stats(accounts(3), None);
+        assert_eq!(stats.allocations, 3);
+        assert_eq!(stats.wins, 1);
+        assert_eq!(stats.losses, 1);
+        assert_eq!(stats.cumulative_entry.0, 5_000);
+        assert_eq!(stats.cumulative_exit.0, 5_200);
+        assert_eq!(stats.pnl_bps.unwrap().0, 400);
+        assert_eq!(stats.best_pnl_bps.unwrap().0, 5_000);
+        assert_eq!(stats.worst_pnl_bps.unwrap().0, -1_000);
+        assert_eq!(stats.average_pnl_bps.unwrap().0, 4_000 / 3);
+        // (5_000 * 1h - 1_000 * 3h) / 4h
+        assert_eq!(stats.time_weighted_pnl_bps.unwrap().0, 500);
+    }
+
+    #[test]
+    fn test_account_stats_empty() {
+        let contract = get_contract();
+        let stats = contract.get_account_stats(accounts(3), None);
+        assert_eq!(stats.allocations, 0);
+        assert!(stats.pnl_bps.is_none());
+        assert!(stats.average_pnl_bps.is_none());
+    }
+
+    #[test]
+    fn test_account_leaderboard() {
+        let mut contract = get_contract();
+
+        // one lucky trade doesn't outweigh a larger loss
+        settle(&mut contract, accounts(3), 100, 200, 0);
+        settle(&mut contract, accounts(3), 1_000, 800, 0);
+        settle(&mut contract, accounts(4), 1_000, 1_050, 0);
+
+        let leaderboard = contract.get_account_leaderboard();
+        assert_eq!(leaderboard.len(), 2);
+        assert_eq!(leaderboard[0].near_account_id, accounts(4));
+        assert_eq!(leaderboard[0].pnl_bps.0, 500);
+        assert_eq!(leaderboard[1].near_account_id, accounts(3));
+        assert_eq!(leaderboard[1].pnl_bps.0, -909);
+
+        assert_eq!(contract.get_leaderboard()[0].near_account_id, accounts(3));
+    }
+
+    #[test]
+    fn test_account_leaderboard_reranks_accounts() {
+        let mut contract = get_contract();
+        contract.set_leaderboard_length(1);
+
+        settle(&mut contract, accounts(3), 1_000, 1_500, 0);
+        settle(&mut contract, accounts(4), 1_000, 1_100, 0);
+        assert_eq!(
+            contract.get_account_leaderboard()[0].near_account_id,
+            accounts(3)
+        );
+
+        // accounts(4) wasn't shown, but keeps its rank once accounts(3) falls behind
+        settle(&mut contract, accounts(3), 1_000, 0, 0);
+        let leaderboard = contract.get_account_leaderboard();
+        assert_eq!(leaderboard.len(), 1);
+        assert_eq!(leaderboard[0].near_account_id, accounts(4));
+        assert_eq!(leaderboard[0].pnl_bps.0, 1_000);
+
+        let page = contract.get_leaderboard_page(LeaderboardKind::Accounts, Some(1), Some(10));
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].near_account_id, accounts(3));
+    }
+}
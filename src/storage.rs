@@ -0,0 +1,401 @@
+use crate::*;
+use near_contract_standards::storage_management::{
+    StorageBalance, StorageBalanceBounds, StorageManagement,
+};
+use near_sdk::{assert_one_yocto, StorageUsage};
+
+#[near(serializers = [borsh])]
+pub struct AccountStorage {
+    // attached by the account through `storage_deposit`
+    pub total: Balance,
+    // bytes of the account's registration, ledger entries and allocations
+    pub used_bytes: StorageUsage,
+}
+
+impl AccountStorage {
+    fn used(&self) -> Balance {
+        self.used_bytes as Balance * env::storage_byte_cost().as_yoctonear()
+    }
+
+    pub(crate) fn available(&self) -> Balance {
+        self.total.saturating_sub(self.used())
+    }
+}
+
+#[near_bindgen]
+impl StorageManagement for Contract {
+    #[payable]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let amount = env::attached_deposit().as_yoctonear();
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let registered = self.storage_accounts.get(&account_id).is_some();
+
+        if registration_only.unwrap_or(false) {
+            let refund = if registered {
+                amount
+            } else {
+                let min_balance = self.storage_balance_bounds().min.as_yoctonear();
+                assert!(
+                    amount >= min_balance,
+                    "The attached deposit is less than the minimum storage balance"
+                );
+                self.internal_storage_deposit(&account_id, min_balance);
+                amount - min_balance
+            };
+            if refund > 0 {
+                Promise::new(env::predecessor_account_id())
+                    .transfer(NearToken::from_yoctonear(refund));
+            }
+        } else {
+            if !registered {
+                assert!(
+                    amount >= self.storage_balance_bounds().min.as_yoctonear(),
+                    "The attached deposit is less than the minimum storage balance"
+                );
+            }
+            self.internal_storage_deposit(&account_id, amount);
+        }
+
+        self.storage_balance_of(account_id).unwrap()
+    }
+
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<NearToken>) -> StorageBalance {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let mut record = self.storage_accounts.get(&account_id).unwrap_or_else(|| {
+            env::panic_str(&format!("The account {} is not registered", account_id))
+        });
+
+        let amount = amount
+            .map(|a| a.as_yoctonear())
+            .unwrap_or(record.available());
+        assert!(
+            amount <= record.available(),
+            "The amount is greater than the available storage balance"
+        );
+
+        if amount > 0 {
+            record.total -= amount;
+            self.storage_accounts.insert(&account_id, &record);
+            Promise::new(account_id.clone()).transfer(NearToken::from_yoctonear(amount));
+        }
+
+        self.storage_balance_of(account_id).unwrap()
+    }
+
+    #[payable]
+    fn storage_unregister(&mut self, #[allow(unused_variables)] force: Option<bool>) -> bool {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let Some(record) = self.storage_accounts.get(&account_id) else {
+            return false;
+        };
+
+        // ledger entries can't be enumerated, so they are never burned and `force` has no effect
+        // allocations are kept as history, so their owners stay registered for good
+        assert!(
+            record.used_bytes <= self.account_storage_usage
+                && self.shares.accounts.get(&account_id).unwrap_or(0) == 0,
+            "Can't unregister the account with deposits or allocations"
+        );
+
+        self.storage_accounts.remove(&account_id);
+        self.shares.accounts.remove(&account_id);
+        if record.total > 0 {
+            Promise::new(account_id).transfer(NearToken::from_yoctonear(record.total));
+        }
+        true
+    }
+
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: env::storage_byte_cost().saturating_mul(self.account_storage_usage.into()),
+            max: None,
+        }
+    }
+
+    fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        self.storage_accounts
+            .get(&account_id)
+            .map(|record| StorageBalance {
+                total: NearToken::from_yoctonear(record.total),
+                available: NearToken::from_yoctonear(record.available()),
+            })
+    }
+}
+
+impl Contract {
+    pub(crate) fn measure_account_storage_usage(&mut self) {
+        let initial_storage = env::storage_usage();
+        let tmp_account_id: AccountId = "a".repeat(64).parse().unwrap();
+        self.storage_accounts.insert(
+            &tmp_account_id,
+            &AccountStorage {
+                total: 0,
+                used_bytes: 0,
+            },
+        );
+        self.shares.accounts.insert(&tmp_account_id, &0);
+        self.account_storage_usage = env::storage_usage() - initial_storage;
+        self.storage_accounts.remove(&tmp_account_id);
+        self.shares.accounts.remove(&tmp_account_id);
+    }
+
+    // upper bound of a new `balances` entry, measured with the longest key
+    pub(crate) fn measure_ledger_entry_storage_usage(&mut self) {
+        let initial_storage = env::storage_usage();
+        let ledger_key = (
+            TwitterNearAccount {
+                twitter_id: TwitterId::MAX,
+                near_account_id: "a".repeat(64).parse().unwrap(),
+            },
+            "b".repeat(64).parse().unwrap(),
+        );
+        self.balances.insert(&ledger_key, &Balance::MAX);
+        self.ledger_entry_storage_usage = env::storage_usage() - initial_storage;
+        self.balances.remove(&ledger_key);
+    }
+
+    // registers the account, including its share balance, or tops up its storage balance
+    pub(crate) fn internal_storage_deposit(&mut self, account_id: &AccountId, amount: Balance) {
+        let mut record = self.storage_accounts.get(account_id).unwrap_or_else(|| {
+            self.shares.internal_register_account(account_id);
+            AccountStorage {
+                total: 0,
+                used_bytes: self.account_storage_usage,
+            }
+        });
+        record.total += amount;
+        self.storage_accounts.insert(account_id, &record);
+    }
+
+    // moves the storage delta since `initial_storage` onto the account, releasing it on shrink
+    // only the vault's own keys, like the share pool, grow at the vault's expense
+    pub(crate) fn internal_track_storage(
+        &mut self,
+        account_id: &AccountId,
+        initial_storage: StorageUsage,
+    ) {
+        let current_storage = env::storage_usage();
+        if current_storage == initial_storage || *account_id == env::current_account_id() {
+            return;
+        }
+        let Some(mut record) = self.storage_accounts.get(account_id) else {
+            assert!(
+                current_storage < initial_storage,
+                "Account is not registered for storage"
+            );
+            return;
+        };
+        if current_storage > initial_storage {
+            record.used_bytes += current_storage - initial_storage;
+        } else {
+            record.used_bytes = record
+                .used_bytes
+                .saturating_sub(initial_storage - current_storage);
+        }
+        self.storage_accounts.insert(account_id, &record);
+    }
+
+    pub(crate) fn assert_storage_covered(&self, account_id: &AccountId) {
+        if *account_id == env::current_account_id() {
+            return;
+        }
+        let record = self
+            .storage_accounts
+            .get(account_id)
+            .expect("Account is not registered for storage");
+        assert!(
+            record.used() <= record.total,
+            "Insufficient storage balance, call storage_deposit"
+        );
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::{fund_allocation, get_context};
+
+    fn get_contract() -> Contract {
+        Contract::new(
+            "agent.near".to_string(),
+            accounts(1),
+            accounts(0),
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_storage_deposit() {
+        let mut context = get_context(accounts(2));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+        let min = contract.storage_balance_bounds().min;
+        assert!(min > NearToken::from_yoctonear(0));
+        assert!(contract.storage_balance_of(accounts(2)).is_none());
+
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let balance = contract.storage_deposit(None, None);
+        assert_eq!(balance.total, NearToken::from_near(1));
+        assert_eq!(
+            balance.available,
+            NearToken::from_near(1).saturating_sub(min)
+        );
+
+        let balance = contract.storage_deposit(None, Some(true));
+        assert_eq!(balance.total, NearToken::from_near(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "The attached deposit is less than the minimum storage balance")]
+    fn test_storage_deposit_below_minimum() {
+        let mut context = get_context(accounts(2));
+        testing_env!(context
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        let mut contract = get_contract();
+
+        contract.storage_deposit(None, None);
+    }
+
+    #[test]
+    fn test_deposit_charges_and_refunds_storage() {
+        let mut context = get_context(accounts(2));
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let mut contract = get_contract();
+        contract.storage_deposit(None, Some(true));
+        let registered = contract.storage_balance_of(accounts(2)).unwrap();
+        assert_eq!(registered.available, NearToken::from_yoctonear(0));
+
+        contract.storage_deposit(None, None);
+        let before = contract.storage_balance_of(accounts(2)).unwrap();
+        contract.deposit_near(U128(1));
+        let after = contract.storage_balance_of(accounts(2)).unwrap();
+        assert!(after.available < before.available);
+
+        // removing the zero balance entry releases the storage again
+        contract.internal_grant_role(&accounts(1), Role::Operator);
+        testing_env!(get_context(accounts(1)).build());
+        contract.withdraw_near(U128(1), accounts(2), None, None);
+        let released = contract.storage_balance_of(accounts(2)).unwrap();
+        assert_eq!(released.available, before.available);
+    }
+
+    #[test]
+    #[should_panic(expected = "Account is not registered for storage")]
+    fn test_deposit_without_storage() {
+        let mut context = get_context(accounts(2));
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let mut contract = get_contract();
+
+        contract.deposit_near(U128(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Insufficient storage balance, call storage_deposit")]
+    fn test_deposit_with_registration_only() {
+        let mut context = get_context(accounts(2));
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let mut contract = get_contract();
+        contract.storage_deposit(None, Some(true));
+
+        contract.deposit_near(U128(1));
+    }
+
+    #[test]
+    fn test_allocation_charges_owner_storage() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+        contract.internal_storage_deposit(&accounts(2), NearToken::from_near(1).as_yoctonear());
+        contract.internal_storage_deposit(&accounts(3), NearToken::from_near(1).as_yoctonear());
+        let before = contract.storage_balance_of(accounts(2)).unwrap();
+
+        let funding = fund_allocation(&mut contract, 1000);
+        contract.create_capital_allocation(accounts(2), funding, None, None);
+        let after = contract.storage_balance_of(accounts(2)).unwrap();
+        assert!(after.available < before.available);
+    }
+
+    #[test]
+    #[should_panic(expected = "Account is not registered for storage")]
+    fn test_unregistered_account_storage_growth() {
+        testing_env!(get_context(accounts(1)).build());
+        let mut contract = get_contract();
+        let key = TwitterNearAccount {
+            twitter_id: 42,
+            near_account_id: accounts(3),
+        };
+        contract.internal_set_balance(&key, &contract.usdc_contract_id(), 1_000);
+    }
+
+    #[test]
+    fn test_owner_pays_proceeds_of_unregistered_depositor() {
+        testing_env!(get_context(accounts(1)).build());
+        let mut contract = get_contract();
+        contract.internal_grant_role(&accounts(1), Role::Operator);
+        contract.internal_storage_deposit(&accounts(2), NearToken::from_near(1).as_yoctonear());
+        contract.internal_storage_deposit(&accounts(3), NearToken::from_near(1).as_yoctonear());
+        let funding = fund_allocation(&mut contract, 1_000);
+        let capital_id = contract.create_capital_allocation(accounts(2), funding, None, Some(0));
+
+        // the depositor's entry was removed with the allocation funding it
+        testing_env!(get_context(accounts(3))
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        assert!(contract.storage_unregister(None));
+
+        testing_env!(get_context(accounts(1))
+            .prepaid_gas(Gas::from_tgas(300))
+            .build());
+        contract.withdraw_capital(capital_id);
+        let before = contract.storage_balance_of(accounts(2)).unwrap();
+        contract.set_capital_exit_value(capital_id, U128(1_100), None);
+        assert_eq!(contract.get_usdc_balance(U128(42), accounts(3)).0, 1_100);
+        let after = contract.storage_balance_of(accounts(2)).unwrap();
+        assert!(after.available < before.available);
+    }
+
+    #[test]
+    fn test_storage_withdraw_and_unregister() {
+        let mut context = get_context(accounts(2));
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let mut contract = get_contract();
+        contract.storage_deposit(None, None);
+
+        testing_env!(context
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        let balance = contract.storage_withdraw(Some(NearToken::from_millinear(100)));
+        assert_eq!(balance.total, NearToken::from_millinear(900));
+
+        assert!(contract.storage_unregister(None));
+        assert!(contract.storage_balance_of(accounts(2)).is_none());
+        assert!(!contract.storage_unregister(None));
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't unregister the account with deposits or allocations")]
+    fn test_storage_unregister_with_deposits() {
+        let mut context = get_context(accounts(2));
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let mut contract = get_contract();
+        contract.storage_deposit(None, None);
+        contract.deposit_near(U128(1));
+
+        testing_env!(context
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.storage_unregister(Some(true));
+    }
+}
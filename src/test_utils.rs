@@ -0,0 +1,72 @@
+use near_sdk::test_utils::{accounts, VMContextBuilder};
+use near_sdk::testing_env;
+
+use crate::*;
+
+// accounts(0) is the vault, accounts(1) the agent and operator, accounts(3) the depositor
+
+pub fn get_context(predecessor_account_id: AccountId) -> VMContextBuilder {
+    let mut builder = VMContextBuilder::new();
+    builder
+        .current_account_id(accounts(0))
+        .signer_account_id(predecessor_account_id.clone())
+        .predecessor_account_id(predecessor_account_id);
+    builder
+}
+
+// callback context with the results of the promises it was chained to
+pub fn set_promise_results(results: Vec<PromiseResult>) {
+    testing_env!(
+        get_context(accounts(0)).build(),
+        near_sdk::test_vm_config(),
+        near_sdk::RuntimeFeesConfig::test(),
+        Default::default(),
+        results,
+    );
+}
+
+// credits USDC to accounts(3) and funds an allocation with all of it
+pub fn fund_allocation(contract: &mut Contract, amount: Balance) -> Vec<FundingSource> {
+    let token_id = contract.usdc_contract_id();
+    fund_allocation_with(contract, &token_id, amount)
+}
+
+// credits accounts(3) with the token and funds an allocation with all of it
+pub fn fund_allocation_with(
+    contract: &mut Contract,
+    token_id: &AccountId,
+    amount: Balance,
+) -> Vec<FundingSource> {
+    let key = TwitterNearAccount {
+        twitter_id: 42,
+        near_account_id: accounts(3),
+    };
+    let balance = contract.internal_get_balance(&key, token_id);
+    contract.internal_set_balance(&key, token_id, balance + amount);
+    vec![FundingSource {
+        twitter_id: U128(42),
+        near_account_id: accounts(3),
+        amount: U128(amount),
+    }]
+}
+
+// allocation of `owner_id` funded by accounts(3), locked for `locktime` and settled at `exit_amount`
+pub fn settle(
+    contract: &mut Contract,
+    owner_id: AccountId,
+    entry_amount: u128,
+    exit_amount: u128,
+    locktime: u64,
+) -> u64 {
+    testing_env!(get_context(accounts(1)).build());
+    let funding = fund_allocation(contract, entry_amount);
+    let capital_id = contract.create_capital_allocation(owner_id, funding, None, Some(locktime));
+
+    testing_env!(get_context(accounts(1))
+        .block_timestamp(locktime * 1_000_000)
+        .prepaid_gas(Gas::from_tgas(300))
+        .build());
+    contract.withdraw_capital(capital_id);
+    contract.set_capital_exit_value(capital_id, U128(exit_amount), None);
+    capital_id
+}
@@ -3,8 +3,31 @@ use crate::*;
 #[derive(Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 pub enum TokenReceiverAction {
-    Deposit { twitter_id: U128, input_tweet_id: Option<U128> },
-    AddCapital { capital_id: u64 },
+    Deposit {
+        twitter_id: U128,
+        input_tweet_id: Option<U128>,
+    },
+    AddCapital {
+        capital_id: u64,
+    },
+    // mints share tokens for the sender at the current price, see `shares`
+    MintShares,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum DepositRejectReason {
+    InvalidMessage,
+    DepositsPaused,
+    TokenNotWhitelisted,
+    BelowMinDeposit,
+    StorageNotRegistered,
+    InsufficientStorage,
+    NotAgent,
+    EmergencyMode,
+    NotShareToken,
+    SharePoolDepleted,
+    SharePoolUnsettled,
 }
 
 #[near_bindgen]
@@ -17,24 +40,242 @@ impl FungibleTokenReceiver for Contract {
     ) -> PromiseOrValue<U128> {
         let token_in = env::predecessor_account_id();
 
-        let message: TokenReceiverAction =
-            serde_json::from_str(&msg).expect("Failed to parse message");
+        let message: TokenReceiverAction = match serde_json::from_str(&msg) {
+            Ok(message) => message,
+            Err(_) => {
+                return reject_deposit(
+                    &sender_id,
+                    &token_in,
+                    amount,
+                    DepositRejectReason::InvalidMessage,
+                )
+            }
+        };
+
+        if self.emergency_mode {
+            return reject_deposit(
+                &sender_id,
+                &token_in,
+                amount,
+                DepositRejectReason::EmergencyMode,
+            );
+        }
 
         match message {
-            TokenReceiverAction::Deposit { twitter_id, input_tweet_id } => {
-                if token_in == USDC_CONTRACT_ID {
-                    self.deposit_usdc(twitter_id, sender_id, amount.0, input_tweet_id);
+            TokenReceiverAction::Deposit {
+                twitter_id,
+                input_tweet_id,
+            } => {
+                if self.pause_status.is_paused(PausableOperation::Deposits) {
+                    return reject_deposit(
+                        &sender_id,
+                        &token_in,
+                        amount,
+                        DepositRejectReason::DepositsPaused,
+                    );
+                }
+                let key = TwitterNearAccount {
+                    twitter_id: twitter_id.0,
+                    near_account_id: sender_id.clone(),
+                };
+                if let Err(reason) = self.check_deposit(&key, &token_in, amount.0) {
+                    return reject_deposit(&sender_id, &token_in, amount, reason);
                 }
+                self.deposit_ft(twitter_id, sender_id, token_in, amount.0, input_tweet_id);
             }
             TokenReceiverAction::AddCapital { capital_id } => {
-                assert_eq!(
-                    sender_id, self.agent_account_id,
-                    "Only agent can add capital"
-                );
+                if !self.internal_has_role(&sender_id, Role::Agent) {
+                    return reject_deposit(
+                        &sender_id,
+                        &token_in,
+                        amount,
+                        DepositRejectReason::NotAgent,
+                    );
+                }
                 self.add_position(capital_id, token_in, amount.0);
             }
+            TokenReceiverAction::MintShares => {
+                if self.pause_status.is_paused(PausableOperation::Deposits) {
+                    return reject_deposit(
+                        &sender_id,
+                        &token_in,
+                        amount,
+                        DepositRejectReason::DepositsPaused,
+                    );
+                }
+                match self.check_mint_shares(&sender_id, &token_in, amount.0) {
+                    Ok(shares) => self.internal_mint_shares(&sender_id, amount.0, shares),
+                    Err(reason) => return reject_deposit(&sender_id, &token_in, amount, reason),
+                }
+            }
         }
 
         PromiseOrValue::Value(U128(0))
     }
 }
+
+// returns the whole amount as unused, so the token contract refunds the sender
+fn reject_deposit(
+    sender_id: &AccountId,
+    token_id: &AccountId,
+    amount: U128,
+    reason: DepositRejectReason,
+) -> PromiseOrValue<U128> {
+    VaultEvent::DepositRejected(DepositRejectedEvent {
+        sender_id: sender_id.clone(),
+        token_id: token_id.clone(),
+        amount,
+        reason,
+    })
+    .emit();
+    PromiseOrValue::Value(amount)
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{accounts, get_logs};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::{fund_allocation, get_context};
+
+    fn get_contract() -> Contract {
+        let mut contract = Contract::new(
+            "agent.near".to_string(),
+            accounts(1),
+            accounts(0),
+            None,
+            None,
+        );
+        contract.internal_storage_deposit(&accounts(1), NearToken::from_near(1).as_yoctonear());
+        contract.internal_storage_deposit(&accounts(3), NearToken::from_near(1).as_yoctonear());
+        contract
+    }
+
+    fn usdc() -> AccountId {
+        AccountId::from_str(DEFAULT_USDC_CONTRACT_ID).unwrap()
+    }
+
+    fn unused_amount(result: PromiseOrValue<U128>) -> u128 {
+        match result {
+            PromiseOrValue::Value(unused) => unused.0,
+            PromiseOrValue::Promise(_) => panic!("Expected a value"),
+        }
+    }
+
+    fn assert_rejected(reason: &str) {
+        let logs = get_logs();
+        let last = logs.last().expect("No events emitted");
+        assert!(last.contains("\"event\":\"deposit_rejected\""));
+        assert!(last.contains(&format!("\"reason\":\"{}\"", reason)));
+    }
+
+    #[test]
+    fn test_deposit_whitelisted_token() {
+        testing_env!(get_context(usdc()).build());
+        let mut contract = get_contract();
+
+        let msg = r#"{"Deposit":{"twitter_id":"42","input_tweet_id":null}}"#.to_string();
+        let unused = contract.ft_on_transfer(accounts(3), U128(200_000), msg);
+        assert_eq!(unused_amount(unused), 0);
+        assert_eq!(contract.get_usdc_balance(U128(42), accounts(3)).0, 200_000);
+    }
+
+    #[test]
+    fn test_deposit_not_whitelisted_token() {
+        testing_env!(get_context(accounts(4)).build());
+        let mut contract = get_contract();
+
+        let msg = r#"{"Deposit":{"twitter_id":"42","input_tweet_id":null}}"#.to_string();
+        let unused = contract.ft_on_transfer(accounts(3), U128(200_000), msg);
+        assert_eq!(unused_amount(unused), 200_000);
+        assert_eq!(
+            contract.get_balance(U128(42), accounts(3), accounts(4)).0,
+            0
+        );
+        assert_rejected("token_not_whitelisted");
+    }
+
+    #[test]
+    fn test_deposit_below_minimum() {
+        testing_env!(get_context(usdc()).build());
+        let mut contract = get_contract();
+
+        let msg = r#"{"Deposit":{"twitter_id":"42","input_tweet_id":null}}"#.to_string();
+        let unused = contract.ft_on_transfer(accounts(3), U128(99_999), msg);
+        assert_eq!(unused_amount(unused), 99_999);
+        assert_eq!(contract.get_usdc_balance(U128(42), accounts(3)).0, 0);
+        assert_rejected("below_min_deposit");
+    }
+
+    #[test]
+    fn test_deposit_storage_not_registered() {
+        testing_env!(get_context(usdc()).build());
+        let mut contract = get_contract();
+
+        let msg = r#"{"Deposit":{"twitter_id":"42","input_tweet_id":null}}"#.to_string();
+        let unused = contract.ft_on_transfer(accounts(2), U128(200_000), msg);
+        assert_eq!(unused_amount(unused), 200_000);
+        assert_eq!(contract.get_usdc_balance(U128(42), accounts(2)).0, 0);
+        assert_rejected("storage_not_registered");
+    }
+
+    #[test]
+    fn test_deposit_insufficient_storage() {
+        testing_env!(get_context(usdc()).build());
+        let mut contract = get_contract();
+        // registered without room for a ledger entry
+        let registration = contract.storage_balance_bounds().min.as_yoctonear();
+        contract.internal_storage_deposit(&accounts(2), registration);
+
+        let msg = r#"{"Deposit":{"twitter_id":"42","input_tweet_id":null}}"#.to_string();
+        let unused = contract.ft_on_transfer(accounts(2), U128(200_000), msg);
+        assert_eq!(unused_amount(unused), 200_000);
+        assert_eq!(contract.get_usdc_balance(U128(42), accounts(2)).0, 0);
+        assert_rejected("insufficient_storage");
+    }
+
+    #[test]
+    fn test_invalid_message() {
+        testing_env!(get_context(usdc()).build());
+        let mut contract = get_contract();
+
+        let unused = contract.ft_on_transfer(accounts(3), U128(200_000), "deposit".to_string());
+        assert_eq!(unused_amount(unused), 200_000);
+        assert_rejected("invalid_message");
+    }
+
+    #[test]
+    fn test_add_capital_by_agent() {
+        testing_env!(get_context(accounts(1)).build());
+        let mut contract = get_contract();
+        let funding = fund_allocation(&mut contract, 1000);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, None);
+
+        testing_env!(get_context(accounts(4)).build());
+        let msg = format!(r#"{{"AddCapital":{{"capital_id":{}}}}}"#, capital_id);
+        let unused = contract.ft_on_transfer(accounts(1), U128(1000), msg);
+        assert_eq!(unused_amount(unused), 0);
+
+        let capital = contract.get_capital(capital_id);
+        assert_eq!(capital.positions.len(), 1);
+        assert_eq!(capital.positions[0].token_id, accounts(4));
+        assert_eq!(capital.positions[0].amount, 1000);
+    }
+
+    #[test]
+    fn test_add_capital_not_agent() {
+        testing_env!(get_context(accounts(1)).build());
+        let mut contract = get_contract();
+        let funding = fund_allocation(&mut contract, 1000);
+        let capital_id = contract.create_capital_allocation(accounts(1), funding, None, None);
+
+        testing_env!(get_context(accounts(4)).build());
+        let msg = format!(r#"{{"AddCapital":{{"capital_id":{}}}}}"#, capital_id);
+        let unused = contract.ft_on_transfer(accounts(3), U128(1000), msg);
+        assert_eq!(unused_amount(unused), 1000);
+        assert!(contract.get_capital(capital_id).positions.is_empty());
+        assert_rejected("not_agent");
+    }
+}
@@ -0,0 +1,323 @@
+use crate::*;
+
+// ledger id for native NEAR deposits made through `deposit_near`
+pub const NATIVE_NEAR_TOKEN_ID: &str = "near";
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct TokenConfig {
+    pub decimals: u8,
+    pub min_deposit: U128,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn whitelist_token(&mut self, token_id: AccountId, decimals: u8, min_deposit: U128) {
+        self.require_role(Role::Owner);
+        self.removed_tokens.remove(&token_id);
+        self.tokens.insert(
+            &token_id,
+            &TokenConfig {
+                decimals,
+                min_deposit,
+            },
+        );
+    }
+
+    // stops new deposits of the token, existing balances can still be withdrawn
+    pub fn remove_token(&mut self, token_id: AccountId) {
+        self.require_role(Role::Owner);
+        self.tokens
+            .remove(&token_id)
+            .expect("Token is not whitelisted");
+        self.removed_tokens.insert(&token_id);
+    }
+
+    pub fn get_token(&self, token_id: AccountId) -> Option<TokenConfig> {
+        self.tokens.get(&token_id)
+    }
+
+    pub fn get_whitelisted_tokens(&self) -> Vec<(AccountId, TokenConfig)> {
+        self.tokens.to_vec()
+    }
+
+    pub fn get_balance(
+        &self,
+        twitter_id: U128,
+        near_account_id: AccountId,
+        token_id: AccountId,
+    ) -> U128 {
+        let key = TwitterNearAccount {
+            twitter_id: twitter_id.0,
+            near_account_id,
+        };
+        U128::from(self.internal_get_balance(&key, &token_id))
+    }
+
+    // non-zero balances across whitelisted and removed tokens
+    pub fn get_balances(
+        &self,
+        twitter_id: U128,
+        near_account_id: AccountId,
+    ) -> Vec<(AccountId, U128)> {
+        let key = TwitterNearAccount {
+            twitter_id: twitter_id.0,
+            near_account_id,
+        };
+        self.tokens
+            .keys()
+            .chain(self.removed_tokens.iter())
+            .map(|token_id| {
+                let balance = self.internal_get_balance(&key, &token_id);
+                (token_id, U128::from(balance))
+            })
+            .filter(|(_, balance)| balance.0 > 0)
+            .collect()
+    }
+}
+
+impl Contract {
+    pub(crate) fn internal_get_balance(
+        &self,
+        key: &TwitterNearAccount,
+        token_id: &AccountId,
+    ) -> Balance {
+        self.balances
+            .get(&(key.clone(), token_id.clone()))
+            .or_else(|| self.internal_get_legacy_balance(key, token_id))
+            .unwrap_or(0)
+    }
+
+    pub(crate) fn internal_set_balance(
+        &mut self,
+        key: &TwitterNearAccount,
+        token_id: &AccountId,
+        balance: Balance,
+    ) {
+        let payer_id = key.near_account_id.clone();
+        self.internal_set_balance_paid_by(key, token_id, balance, &payer_id);
+    }
+
+    // like `internal_set_balance`, with the storage delta moved onto `payer_id` instead of the account
+    pub(crate) fn internal_set_balance_paid_by(
+        &mut self,
+        key: &TwitterNearAccount,
+        token_id: &AccountId,
+        balance: Balance,
+        payer_id: &AccountId,
+    ) {
+        let ledger_key = (key.clone(), token_id.clone());
+        let initial_storage = env::storage_usage();
+        // v0 didn't charge storage, the vault keeps paying for entries moved from there
+        let payer_id = if self.internal_remove_legacy_balance(key, token_id) {
+            env::current_account_id()
+        } else {
+            payer_id.clone()
+        };
+        if balance == 0 {
+            self.balances.remove(&ledger_key);
+        } else {
+            self.balances.insert(&ledger_key, &balance);
+        }
+        self.internal_track_storage(&payer_id, initial_storage);
+    }
+
+    // credits funds owed to an account which may have unregistered since it last held a balance,
+    // e.g. a failed payout or settlement proceeds, `payer_id` then pays for the new entry
+    pub(crate) fn internal_credit_balance(
+        &mut self,
+        key: &TwitterNearAccount,
+        token_id: &AccountId,
+        amount: Balance,
+        payer_id: &AccountId,
+    ) {
+        let balance = self.internal_get_balance(key, token_id) + amount;
+        if self.storage_accounts.get(&key.near_account_id).is_some() {
+            self.internal_set_balance(key, token_id, balance);
+        } else {
+            self.internal_set_balance_paid_by(key, token_id, balance, payer_id);
+        }
+    }
+
+    pub(crate) fn internal_deposit(
+        &mut self,
+        key: &TwitterNearAccount,
+        token_id: &AccountId,
+        amount: Balance,
+    ) {
+        let token = self.tokens.get(token_id).expect("Token is not whitelisted");
+
+        let new_balance = self.internal_get_balance(key, token_id) + amount;
+        assert!(
+            new_balance >= token.min_deposit.0,
+            "Deposit must be at least {} of {}",
+            token.min_deposit.0,
+            token_id
+        );
+
+        self.internal_set_balance(key, token_id, new_balance);
+        self.assert_storage_covered(&key.near_account_id);
+    }
+
+    // same checks as `internal_deposit`, without panicking
+    pub(crate) fn check_deposit(
+        &self,
+        key: &TwitterNearAccount,
+        token_id: &AccountId,
+        amount: Balance,
+    ) -> Result<(), DepositRejectReason> {
+        let token = self
+            .tokens
+            .get(token_id)
+            .ok_or(DepositRejectReason::TokenNotWhitelisted)?;
+        if self.internal_get_balance(key, token_id) + amount < token.min_deposit.0 {
+            return Err(DepositRejectReason::BelowMinDeposit);
+        }
+        let Some(storage) = self.storage_accounts.get(&key.near_account_id) else {
+            return Err(DepositRejectReason::StorageNotRegistered);
+        };
+        let ledger_key = (key.clone(), token_id.clone());
+        if !self.balances.contains_key(&ledger_key)
+            && storage.available()
+                < self.ledger_entry_storage_usage as Balance
+                    * env::storage_byte_cost().as_yoctonear()
+        {
+            return Err(DepositRejectReason::InsufficientStorage);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn internal_withdraw(
+        &mut self,
+        key: &TwitterNearAccount,
+        token_id: &AccountId,
+        amount: Balance,
+    ) {
+        let balance = self.internal_get_balance(key, token_id);
+        assert!(balance >= amount, "Not enough balance to withdraw");
+        self.internal_set_balance(key, token_id, balance - amount);
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::get_context;
+
+    fn get_contract() -> Contract {
+        let mut contract = Contract::new(
+            "agent.near".to_string(),
+            accounts(1),
+            accounts(0),
+            None,
+            None,
+        );
+        contract.internal_storage_deposit(&accounts(1), NearToken::from_near(1).as_yoctonear());
+        contract.internal_storage_deposit(&accounts(3), NearToken::from_near(1).as_yoctonear());
+        contract
+    }
+
+    fn usdt() -> AccountId {
+        AccountId::from_str("usdt.tether-token.near").unwrap()
+    }
+
+    #[test]
+    fn test_default_tokens() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let contract = get_contract();
+
+        let near = contract
+            .get_token(AccountId::from_str(NATIVE_NEAR_TOKEN_ID).unwrap())
+            .unwrap();
+        assert_eq!(near.decimals, 24);
+        assert_eq!(near.min_deposit.0, MIN_NEAR_DEPOSIT.as_yoctonear());
+
+        let usdc = contract
+            .get_token(AccountId::from_str(DEFAULT_USDC_CONTRACT_ID).unwrap())
+            .unwrap();
+        assert_eq!(usdc.decimals, USDC_DECIMALS);
+        assert_eq!(usdc.min_deposit.0, MIN_USDC_DEPOSIT);
+        assert_eq!(contract.get_whitelisted_tokens().len(), 2);
+    }
+
+    #[test]
+    fn test_deposit_whitelisted_token() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+        contract.whitelist_token(usdt(), 6, U128(1_000));
+
+        let twitter_id = U128(1845765845647056907);
+        contract.deposit_ft(twitter_id, accounts(3), usdt(), 5_000, None);
+        contract.deposit_ft(
+            twitter_id,
+            accounts(3),
+            AccountId::from_str(DEFAULT_USDC_CONTRACT_ID).unwrap(),
+            200_000,
+            None,
+        );
+
+        assert_eq!(
+            contract.get_balance(twitter_id, accounts(3), usdt()).0,
+            5_000
+        );
+        assert_eq!(
+            contract.get_usdc_balance(twitter_id, accounts(3)).0,
+            200_000
+        );
+
+        let balances = contract.get_balances(twitter_id, accounts(3));
+        assert_eq!(balances.len(), 2);
+        assert!(balances.contains(&(usdt(), U128(5_000))));
+        assert!(balances.contains(&(
+            AccountId::from_str(DEFAULT_USDC_CONTRACT_ID).unwrap(),
+            U128(200_000)
+        )));
+    }
+
+    #[test]
+    #[should_panic(expected = "Token is not whitelisted")]
+    fn test_deposit_unknown_token() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+
+        contract.deposit_ft(U128(1), accounts(3), usdt(), 5_000, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Deposit must be at least 1000 of usdt.tether-token.near")]
+    fn test_deposit_below_minimum() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+        contract.whitelist_token(usdt(), 6, U128(1_000));
+
+        contract.deposit_ft(U128(1), accounts(3), usdt(), 999, None);
+    }
+
+    #[test]
+    fn test_remove_token_keeps_balance() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = get_contract();
+        contract.whitelist_token(usdt(), 6, U128(1_000));
+        contract.deposit_ft(U128(1), accounts(3), usdt(), 5_000, None);
+
+        contract.remove_token(usdt());
+        assert!(contract.get_token(usdt()).is_none());
+        assert_eq!(contract.get_balance(U128(1), accounts(3), usdt()).0, 5_000);
+        assert_eq!(
+            contract.get_balances(U128(1), accounts(3)),
+            vec![(usdt(), U128(5_000))]
+        );
+
+        // whitelisted again, the balance is listed once
+        contract.whitelist_token(usdt(), 6, U128(1_000));
+        assert_eq!(contract.get_balances(U128(1), accounts(3)).len(), 1);
+    }
+}
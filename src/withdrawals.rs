@@ -0,0 +1,541 @@
+use crate::*;
+use near_sdk::IntoStorageKey;
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct WithdrawalRequest {
+    pub twitter_id: U128,
+    pub near_account_id: AccountId,
+    pub token_id: AccountId,
+    // debited from the balance when requested, re-credited if rejected
+    pub amount: U128,
+    pub created_timestamp: Timestamp,
+    // anyone can release the request from then on
+    pub release_timestamp: Timestamp,
+}
+
+fn account_requests_prefix(account_id: &AccountId) -> Vec<u8> {
+    let mut prefix = StorageKey::WithdrawalRequestsByAccount.into_storage_key();
+    prefix.extend(env::sha256(account_id.as_bytes()));
+    prefix
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn request_withdrawal(
+        &mut self,
+        twitter_id: U128,
+        token_id: AccountId,
+        amount: U128,
+    ) -> u64 {
+        self.assert_not_paused(PausableOperation::Withdrawals);
+        assert!(amount.0 > 0, "Withdrawal amount must be positive");
+        let key = TwitterNearAccount {
+            twitter_id: twitter_id.0,
+            near_account_id: env::predecessor_account_id(),
+        };
+        self.internal_withdraw(&key, &token_id, amount.0);
+
+        let request = WithdrawalRequest {
+            twitter_id,
+            near_account_id: key.near_account_id,
+            token_id,
+            amount,
+            created_timestamp: env::block_timestamp_ms(),
+            release_timestamp: env::block_timestamp_ms() + self.withdrawal_timeout,
+        };
+        let request_id = self.next_withdrawal_request_id;
+        let initial_storage = env::storage_usage();
+        self.withdrawal_requests.insert(&request_id, &request);
+        let mut request_ids = self
+            .withdrawal_requests_by_account
+            .get(&request.near_account_id)
+            .unwrap_or_else(|| {
+                UnorderedSet::new(account_requests_prefix(&request.near_account_id))
+            });
+        request_ids.insert(&request_id);
+        self.withdrawal_requests_by_account
+            .insert(&request.near_account_id, &request_ids);
+        self.internal_track_storage(&request.near_account_id, initial_storage);
+        self.assert_storage_covered(&request.near_account_id);
+        self.next_withdrawal_request_id += 1;
+
+        VaultEvent::WithdrawalRequested(WithdrawalRequestedEvent {
+            request_id,
+            account_id: request.near_account_id,
+            twitter_id,
+            token_id: request.token_id,
+            amount,
+            release_timestamp: request.release_timestamp,
+        })
+        .emit();
+
+        request_id
+    }
+
+    pub fn approve_withdrawal(&mut self, request_id: u64) {
+        self.require_role(Role::Operator);
+        self.assert_not_paused(PausableOperation::Withdrawals);
+        self.assert_not_emergency();
+        self.internal_release_withdrawal(request_id);
+    }
+
+    pub fn reject_withdrawal(&mut self, request_id: u64) {
+        self.require_role(Role::Operator);
+        let request = self.internal_remove_withdrawal_request(request_id);
+        let key = TwitterNearAccount {
+            twitter_id: request.twitter_id.0,
+            near_account_id: request.near_account_id.clone(),
+        };
+        let balance = self.internal_get_balance(&key, &request.token_id);
+        self.internal_set_balance(&key, &request.token_id, balance + request.amount.0);
+
+        VaultEvent::WithdrawalRejected(WithdrawalRejectedEvent {
+            request_id,
+            account_id: request.near_account_id,
+            token_id: request.token_id,
+            amount: request.amount,
+        })
+        .emit();
+    }
+
+    // pays out a request nobody approved or rejected in time, callable by anyone
+    // in emergency mode requests are released right away and, like `emergency_withdraw`, despite pauses
+    pub fn release_withdrawal(&mut self, request_id: u64) {
+        if !self.emergency_mode {
+            self.assert_not_paused(PausableOperation::Withdrawals);
+        }
+        let request = self
+            .withdrawal_requests
+            .get(&request_id)
+            .expect("Withdrawal request not found");
+        assert!(
+            self.emergency_mode || env::block_timestamp_ms() >= request.release_timestamp,
+            "Withdrawal request is still awaiting approval"
+        );
+        self.internal_release_withdrawal(request_id);
+    }
+
+    // applies to requests made from now on
+    pub fn set_withdrawal_timeout(&mut self, withdrawal_timeout: u64) {
+        self.require_role(Role::Owner);
+        self.withdrawal_timeout = withdrawal_timeout;
+    }
+
+    pub fn get_withdrawal_timeout(&self) -> u64 {
+        self.withdrawal_timeout
+    }
+
+    pub fn get_withdrawal_request(&self, request_id: u64) -> Option<WithdrawalRequest> {
+        self.withdrawal_requests.get(&request_id)
+    }
+
+    // pending requests, optionally of a single depositor
+    pub fn get_withdrawal_requests(
+        &self,
+        account_id: Option<AccountId>,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<(u64, WithdrawalRequest)> {
+        let from_index = from_index.unwrap_or(0);
+        match account_id {
+            Some(account_id) => match self.withdrawal_requests_by_account.get(&account_id) {
+                Some(request_ids) => {
+                    self.internal_page_requests(request_ids.as_vector(), from_index, limit)
+                }
+                None => vec![],
+            },
+            None => self.internal_page_requests(
+                self.withdrawal_requests.keys_as_vector(),
+                from_index,
+                limit,
+            ),
+        }
+    }
+
+    // lets the operator pay the caller's NEAR withdrawals out to `receiver_id`
+    pub fn add_withdrawal_recipient(&mut self, receiver_id: AccountId) {
+        let account_id = env::predecessor_account_id();
+        let mut recipients = self.get_withdrawal_recipients(account_id.clone());
+        if recipients.contains(&receiver_id) {
+            return;
+        }
+        recipients.push(receiver_id);
+
+        let initial_storage = env::storage_usage();
+        self.withdrawal_recipients.insert(&account_id, &recipients);
+        self.internal_track_storage(&account_id, initial_storage);
+        self.assert_storage_covered(&account_id);
+    }
+
+    pub fn remove_withdrawal_recipient(&mut self, receiver_id: AccountId) {
+        let account_id = env::predecessor_account_id();
+        let mut recipients = self.get_withdrawal_recipients(account_id.clone());
+        recipients.retain(|r| *r != receiver_id);
+
+        let initial_storage = env::storage_usage();
+        if recipients.is_empty() {
+            self.withdrawal_recipients.remove(&account_id);
+        } else {
+            self.withdrawal_recipients.insert(&account_id, &recipients);
+        }
+        self.internal_track_storage(&account_id, initial_storage);
+    }
+
+    pub fn get_withdrawal_recipients(&self, account_id: AccountId) -> Vec<AccountId> {
+        self.withdrawal_recipients
+            .get(&account_id)
+            .unwrap_or_default()
+    }
+}
+
+impl Contract {
+    // sends an amount already debited from the balance, `on_withdraw_*` re-credits it on failure
+    pub(crate) fn internal_payout(
+        &self,
+        key: TwitterNearAccount,
+        token_id: AccountId,
+        amount: U128,
+        receiver_id: AccountId,
+    ) -> Promise {
+        if token_id.as_str() == NATIVE_NEAR_TOKEN_ID {
+            Promise::new(receiver_id.clone())
+                .transfer(NearToken::from_yoctonear(amount.0))
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(GAS_RESOLVE_WITHDRAW)
+                        .on_withdraw_near(
+                            U128::from(key.twitter_id),
+                            key.near_account_id,
+                            amount,
+                            receiver_id,
+                        ),
+                )
+        } else {
+            ext_ft::ext(token_id.clone())
+                .with_static_gas(self.config.gas_ft_transfer)
+                .with_attached_deposit(NearToken::from_yoctonear(1))
+                .ft_transfer(receiver_id.clone(), amount, None)
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(GAS_RESOLVE_WITHDRAW)
+                        .on_withdraw_ft(
+                            U128::from(key.twitter_id),
+                            key.near_account_id,
+                            token_id,
+                            amount,
+                            receiver_id,
+                        ),
+                )
+        }
+    }
+
+    fn internal_release_withdrawal(&mut self, request_id: u64) {
+        let request = self.internal_remove_withdrawal_request(request_id);
+        let key = TwitterNearAccount {
+            twitter_id: request.twitter_id.0,
+            near_account_id: request.near_account_id.clone(),
+        };
+        self.internal_payout(
+            key,
+            request.token_id,
+            request.amount,
+            request.near_account_id,
+        );
+    }
+
+    fn internal_page_requests(
+        &self,
+        request_ids: &Vector<u64>,
+        from_index: u64,
+        limit: Option<u64>,
+    ) -> Vec<(u64, WithdrawalRequest)> {
+        let to_index = from_index
+            .saturating_add(page_limit(limit))
+            .min(request_ids.len());
+        (from_index..to_index)
+            .map(|index| {
+                let request_id = request_ids.get(index).unwrap();
+                (
+                    request_id,
+                    self.withdrawal_requests.get(&request_id).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn internal_remove_withdrawal_request(&mut self, request_id: u64) -> WithdrawalRequest {
+        let initial_storage = env::storage_usage();
+        let request = self
+            .withdrawal_requests
+            .remove(&request_id)
+            .expect("Withdrawal request not found");
+        let account_id = &request.near_account_id;
+        let mut request_ids = self.withdrawal_requests_by_account.get(account_id).unwrap();
+        request_ids.remove(&request_id);
+        if request_ids.is_empty() {
+            self.withdrawal_requests_by_account.remove(account_id);
+        } else {
+            self.withdrawal_requests_by_account
+                .insert(account_id, &request_ids);
+        }
+        self.internal_track_storage(&request.near_account_id, initial_storage);
+        request
+    }
+
+    pub(crate) fn assert_withdrawal_recipient(
+        &self,
+        near_account_id: &AccountId,
+        receiver_id: &AccountId,
+    ) {
+        assert!(
+            receiver_id == near_account_id
+                || self
+                    .get_withdrawal_recipients(near_account_id.clone())
+                    .contains(receiver_id),
+            "Receiver is not an allowed withdrawal recipient"
+        );
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_logs};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::{get_context, set_promise_results};
+
+    // accounts(3) deposits 2 NEAR, accounts(1) is the operator
+    fn get_contract() -> Contract {
+        testing_env!(get_context(accounts(3))
+            .attached_deposit(NearToken::from_near(2))
+            .build());
+        let mut contract = Contract::new(
+            "agent.near".to_string(),
+            accounts(1),
+            accounts(0),
+            None,
+            None,
+        );
+        contract.internal_storage_deposit(&accounts(3), NearToken::from_near(1).as_yoctonear());
+        contract.internal_grant_role(&accounts(1), Role::Operator);
+        contract.deposit_near(U128(42));
+        contract
+    }
+
+    #[test]
+    fn test_partial_withdraw_near_to_depositor() {
+        let mut contract = get_contract();
+
+        testing_env!(get_context(accounts(1)).build());
+        let amount = U128(NearToken::from_near(1).as_yoctonear());
+        contract.withdraw_near(U128(42), accounts(3), Some(amount), None);
+        assert_eq!(contract.get_near_balance(U128(42), accounts(3)), amount);
+
+        set_promise_results(vec![PromiseResult::Successful(vec![])]);
+        assert!(contract.on_withdraw_near(U128(42), accounts(3), amount, accounts(3)));
+        assert_eq!(contract.get_near_balance(U128(42), accounts(3)), amount);
+        let logs = get_logs();
+        assert!(logs[0].contains(&format!("\"receiver_id\":\"{}\"", accounts(3))));
+        assert!(logs[0].contains("\"success\":true"));
+    }
+
+    #[test]
+    fn test_withdraw_near_to_allowed_recipient() {
+        let mut contract = get_contract();
+        contract.add_withdrawal_recipient(accounts(4));
+        assert_eq!(
+            contract.get_withdrawal_recipients(accounts(3)),
+            vec![accounts(4)]
+        );
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.withdraw_near(U128(42), accounts(3), None, Some(accounts(4)));
+        assert_eq!(contract.get_near_balance(U128(42), accounts(3)).0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Receiver is not an allowed withdrawal recipient")]
+    fn test_withdraw_near_to_removed_recipient() {
+        let mut contract = get_contract();
+        contract.add_withdrawal_recipient(accounts(4));
+        contract.remove_withdrawal_recipient(accounts(4));
+        assert!(contract.get_withdrawal_recipients(accounts(3)).is_empty());
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.withdraw_near(U128(42), accounts(3), None, Some(accounts(4)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Receiver is not an allowed withdrawal recipient")]
+    fn test_withdraw_near_to_agent_account() {
+        let mut contract = get_contract();
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.withdraw_near(U128(42), accounts(3), None, Some(accounts(1)));
+    }
+
+    #[test]
+    fn test_withdraw_near_failure_recredits() {
+        let mut contract = get_contract();
+
+        testing_env!(get_context(accounts(1)).build());
+        let amount = U128(NearToken::from_near(2).as_yoctonear());
+        contract.withdraw_near(U128(42), accounts(3), None, None);
+        assert_eq!(contract.get_near_balance(U128(42), accounts(3)).0, 0);
+
+        set_promise_results(vec![PromiseResult::Failed]);
+        assert!(!contract.on_withdraw_near(U128(42), accounts(3), amount, accounts(3)));
+        assert_eq!(contract.get_near_balance(U128(42), accounts(3)), amount);
+        assert!(get_logs()[0].contains("\"success\":false"));
+    }
+
+    #[test]
+    fn test_request_and_approve_withdrawal() {
+        let mut contract = get_contract();
+        let near = AccountId::from_str(NATIVE_NEAR_TOKEN_ID).unwrap();
+        let amount = U128(NearToken::from_near(1).as_yoctonear());
+
+        let request_id = contract.request_withdrawal(U128(42), near.clone(), amount);
+        assert_eq!(contract.get_near_balance(U128(42), accounts(3)), amount);
+        let pending = contract.get_withdrawal_requests(Some(accounts(3)), None, None);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].0, request_id);
+        assert_eq!(pending[0].1.amount, amount);
+        assert!(contract
+            .get_withdrawal_requests(Some(accounts(4)), None, None)
+            .is_empty());
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.approve_withdrawal(request_id);
+        assert!(contract.get_withdrawal_request(request_id).is_none());
+
+        set_promise_results(vec![PromiseResult::Successful(vec![])]);
+        assert!(contract.on_withdraw_near(U128(42), accounts(3), amount, accounts(3)));
+        assert_eq!(contract.get_near_balance(U128(42), accounts(3)), amount);
+    }
+
+    #[test]
+    fn test_withdrawal_requests_by_account() {
+        let mut contract = get_contract();
+        let near = AccountId::from_str(NATIVE_NEAR_TOKEN_ID).unwrap();
+        let request_ids: Vec<u64> = (0..3)
+            .map(|_| contract.request_withdrawal(U128(42), near.clone(), U128(1)))
+            .collect();
+
+        let page = contract.get_withdrawal_requests(Some(accounts(3)), Some(1), Some(1));
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].0, request_ids[1]);
+        assert_eq!(contract.get_withdrawal_requests(None, None, None).len(), 3);
+
+        testing_env!(get_context(accounts(1)).build());
+        for request_id in request_ids {
+            contract.reject_withdrawal(request_id);
+        }
+        assert!(contract
+            .get_withdrawal_requests(Some(accounts(3)), None, None)
+            .is_empty());
+        assert!(contract
+            .withdrawal_requests_by_account
+            .get(&accounts(3))
+            .is_none());
+    }
+
+    #[test]
+    fn test_reject_withdrawal_recredits() {
+        let mut contract = get_contract();
+        let near = AccountId::from_str(NATIVE_NEAR_TOKEN_ID).unwrap();
+        let balance = contract.get_near_balance(U128(42), accounts(3));
+
+        let request_id = contract.request_withdrawal(U128(42), near, balance);
+        assert_eq!(contract.get_near_balance(U128(42), accounts(3)).0, 0);
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.reject_withdrawal(request_id);
+        assert_eq!(contract.get_near_balance(U128(42), accounts(3)), balance);
+        assert!(contract
+            .get_withdrawal_requests(None, None, None)
+            .is_empty());
+        assert!(get_logs()[0].contains("\"event\":\"withdrawal_rejected\""));
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires Operator role")]
+    fn test_depositor_cannot_approve_withdrawal() {
+        let mut contract = get_contract();
+        let near = AccountId::from_str(NATIVE_NEAR_TOKEN_ID).unwrap();
+        let request_id = contract.request_withdrawal(U128(42), near, U128(1));
+
+        contract.approve_withdrawal(request_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "Withdrawal request is still awaiting approval")]
+    fn test_release_withdrawal_before_timeout() {
+        let mut contract = get_contract();
+        let near = AccountId::from_str(NATIVE_NEAR_TOKEN_ID).unwrap();
+        let request_id = contract.request_withdrawal(U128(42), near, U128(1));
+
+        contract.release_withdrawal(request_id);
+    }
+
+    #[test]
+    fn test_release_withdrawal_after_timeout() {
+        // accounts(3) deployed the contract and is the owner
+        let mut contract = get_contract();
+        contract.set_withdrawal_timeout(1_000);
+        assert_eq!(contract.get_withdrawal_timeout(), 1_000);
+
+        let near = AccountId::from_str(NATIVE_NEAR_TOKEN_ID).unwrap();
+        let request_id = contract.request_withdrawal(U128(42), near, U128(1));
+        let request = contract.get_withdrawal_request(request_id).unwrap();
+        assert_eq!(request.release_timestamp, request.created_timestamp + 1_000);
+
+        // anyone can release the request once the agent missed the window
+        testing_env!(get_context(accounts(4))
+            .block_timestamp(request.release_timestamp * 1_000_000)
+            .build());
+        contract.release_withdrawal(request_id);
+        assert!(contract.get_withdrawal_request(request_id).is_none());
+    }
+
+    #[test]
+    fn test_release_withdrawal_in_emergency_while_paused() {
+        // accounts(3) deployed the contract and is the owner
+        let mut contract = get_contract();
+        let near = AccountId::from_str(NATIVE_NEAR_TOKEN_ID).unwrap();
+        let request_id = contract.request_withdrawal(U128(42), near, U128(1));
+
+        contract.grant_role(accounts(3), Role::Pauser);
+        contract.grant_role(accounts(3), Role::Guardian);
+        contract.set_paused(PausableOperation::Withdrawals, true);
+        contract.set_emergency_mode(true);
+
+        testing_env!(get_context(accounts(4)).build());
+        contract.release_withdrawal(request_id);
+        assert!(contract.get_withdrawal_request(request_id).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Withdrawals are paused")]
+    fn test_release_withdrawal_while_paused() {
+        let mut contract = get_contract();
+        contract.set_withdrawal_timeout(0);
+        let near = AccountId::from_str(NATIVE_NEAR_TOKEN_ID).unwrap();
+        let request_id = contract.request_withdrawal(U128(42), near, U128(1));
+
+        contract.grant_role(accounts(3), Role::Pauser);
+        contract.set_paused(PausableOperation::Withdrawals, true);
+        contract.release_withdrawal(request_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough balance to withdraw")]
+    fn test_request_withdrawal_above_balance() {
+        let mut contract = get_contract();
+        let near = AccountId::from_str(NATIVE_NEAR_TOKEN_ID).unwrap();
+
+        contract.request_withdrawal(U128(42), near, U128(NearToken::from_near(3).as_yoctonear()));
+    }
+}